@@ -0,0 +1,100 @@
+//! Mate the pairs of a simulated ARTIC v4.1 run of 2x250bp reads with 1%
+//! substitutions, timing the packed scorer against scoring one base at a
+//! time. Run with `cargo bench --bench mating`
+
+use ampliconlib::mating::{mate, MatingParams};
+use ampliconlib::packed::PackedSeq;
+use ampliconlib::primerset::PrimerSet;
+use bio_seq::prelude::*;
+use std::cmp;
+use std::time::Instant;
+
+const BASES: [Dna; 4] = [Dna::A, Dna::C, Dna::G, Dna::T];
+
+const PAIRS_PER_AMPLICON: usize = 50;
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+/// The first 250 bases of a fragment with 1% substitutions
+fn sequence(fragment: &SeqSlice<Dna>, state: &mut u64) -> Seq<Dna> {
+    fragment[..250]
+        .into_iter()
+        .map(|base| {
+            let r = xorshift(state);
+            if r % 100 == 0 {
+                BASES[(base as usize + 1 + (r >> 32) as usize % 3) % 4]
+            } else {
+                base
+            }
+        })
+        .collect()
+}
+
+fn main() {
+    let mut state = 0x9e37_79b9_7f4a_7c15;
+
+    let genome: Seq<Dna> = (0..29903)
+        .map(|_| BASES[(xorshift(&mut state) >> 32) as usize % 4])
+        .collect();
+    let scheme = PrimerSet::builtin("artic-v4.1").unwrap();
+    let mut pairs = Vec::new();
+    for target in scheme.targets.values() {
+        let fragment = &genome[target.start..target.end];
+        for _ in 0..PAIRS_PER_AMPLICON {
+            let r1 = sequence(fragment, &mut state);
+            let r2 = sequence(&fragment.revcomp(), &mut state);
+            let hint = fragment.len() / 2;
+            pairs.push((r1, r2.revcomp(), hint));
+        }
+    }
+    let params = MatingParams::default();
+    // every overlap that mate considers
+    let candidates = |r1: &Seq<Dna>, hint: usize| {
+        let x = (r1.len() - hint) * 2;
+        x.saturating_sub(params.max_span)..=cmp::min(x + params.max_span, r1.len())
+    };
+
+    let start = Instant::now();
+    let mut scalar = 0;
+    for (r1, r2, hint) in &pairs {
+        for l in candidates(r1, *hint) {
+            let (a, b) = (&r2[..l], &r1[r1.len() - l..]);
+            scalar += (0..l).filter(|&i| a[i] != b[i]).count();
+        }
+    }
+    let scalar_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut packed = 0;
+    for (r1, r2, hint) in &pairs {
+        let (p1, p2) = (PackedSeq::new(r1), PackedSeq::new(r2));
+        for l in candidates(r1, *hint) {
+            packed += p2.mismatches(0, &p1, r1.len() - l, l);
+        }
+    }
+    let packed_time = start.elapsed();
+    assert_eq!(packed, scalar);
+
+    let start = Instant::now();
+    let merged = pairs
+        .iter()
+        .filter(|(r1, r2, hint)| mate(r1, r2, *hint, &params).is_some_and(|m| m.accepted))
+        .count();
+    let mate_time = start.elapsed();
+    assert!(merged * 100 >= pairs.len() * 95, "{}", merged);
+
+    println!(
+        "{} pairs: scalar scoring {:?}, packed scoring {:?} ({:.1}x), mate {:?} ({} merged)",
+        pairs.len(),
+        scalar_time,
+        packed_time,
+        scalar_time.as_secs_f64() / packed_time.as_secs_f64(),
+        mate_time,
+        merged
+    );
+}
@@ -191,9 +191,43 @@ pub fn merge_bin(bin: &HashMap<Seq<Dna>, Assembly>) -> HashMap<Seq<Dna>, Assembl
     new.insert(template, assembly);
     new
 }
+
+/// Lay amplicon inserts out along a reference of length `len`, starting from
+/// their reference coordinates. Earlier inserts take precedence where they
+/// overlap and uncovered positions are `N`.
+pub fn tile(len: usize, inserts: &[(usize, &SeqSlice<Dna>)]) -> String {
+    let mut consensus: Vec<Option<Dna>> = vec![None; len];
+    for (start, insert) in inserts {
+        for i in 0..insert.len().min(len.saturating_sub(*start)) {
+            let pos = start + i;
+            if consensus[pos].is_none() {
+                consensus[pos] = Some(insert[i]);
+            }
+        }
+    }
+    consensus
+        .into_iter()
+        .map(|base| match base {
+            Some(Dna::A) => 'A',
+            Some(Dna::C) => 'C',
+            Some(Dna::G) => 'G',
+            Some(Dna::T) => 'T',
+            None => 'N',
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{edit_dist, Cigar};
+    use super::{edit_dist, tile, Cigar};
+    use bio_seq::prelude::*;
+
+    #[test]
+    fn test_tile() {
+        let a: Seq<Dna> = "AAAACC".try_into().unwrap();
+        let b: Seq<Dna> = "GGTTTT".try_into().unwrap();
+        assert_eq!(tile(16, &[(2, &a), (6, &b)]), "NNAAAACCTTTTNNNN");
+    }
 
     #[test]
     fn test_matches() {
@@ -1,3 +1,8 @@
 pub mod aligner;
 pub mod mating;
+pub mod packed;
 pub mod primerset;
+pub mod primersites;
+pub mod schemes;
+pub mod trie;
+pub mod validate;
@@ -3,20 +3,35 @@
 //! # Example
 //!
 //! ```
-//! use bio::pattern_matching::mating::{mate,merge};
-//! let read1 = b"tacgattcgat";
-//! let read2 = b"ttcgattacgt";
-//! let overlap = mate(read1, read2, 1, 1).unwrap();
-//! let contig = merge(read1, read2, overlap);
-//! assert_eq!(contig, b"tacgattcgattacgt");
+//! use ampliconlib::mating::{mate, merge, MatingParams};
+//! use bio_seq::prelude::*;
+//!
+//! let read1: Seq<Dna> = "TACGATTCGAT".try_into().unwrap();
+//! let read2: Seq<Dna> = "TTCGATTACGT".try_into().unwrap();
+//! let params = MatingParams {
+//!     min_overlap: 3,
+//!     min_score: 3,
+//!     max_span: 3,
+//!     ..Default::default()
+//! };
+//! let mating = mate(&read1, &read2, 8, &params).unwrap();
+//! assert!(mating.accepted);
+//! let contig = merge(&read1, &read2, mating.index).unwrap();
+//! assert_eq!(contig.to_string(), "TACGATTCGATTACGT");
 //! ```
 //!
 //! Mate pair merging proceeds in two steps:
-//! * mate: identifying the optimal overlap length or rejecting the case
+//! * mate: identifying the optimal overlap length and accepting or rejecting
+//!   it, see `Mating`
 //! * merge: combines overlapping sequences
 //!
-//! Mating is governed by an objective function and merging resolves
-//! conflicts between sequences.
+//! Mating is governed by an objective function (see `MatingObjective`) and
+//! merging resolves conflicts between sequences. Mismatches of candidate
+//! overlaps are counted on 2-bit packed reads, a word at a time (see
+//! `packed`). With base qualities, each
+//! overlapping position is reconciled by its posterior probability, as in
+//! PEAR: agreeing bases reinforce each other and disagreements take the
+//! higher quality base with a correspondingly reduced quality.
 //!
 //! We also need to define the scores for which mating is acceptable. The
 //! minimum overlap score and minimum overlap length could be guessed
@@ -24,89 +39,796 @@
 
 use bio_seq::prelude::*;
 use std::cmp;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 
-/// Determine the index of overlap for two reads.
+use crate::aligner::Cigar;
+use crate::packed::PackedSeq;
+
+/// Phred quality assumed for reads that come without base qualities
+pub const UNKNOWN_QUAL: u8 = 20;
+
+/// A candidate overlap of the end of one mate with the start of the other
+#[derive(Debug, Clone, Copy)]
+pub struct Overlap<'a> {
+    /// The overlapping bases of the first mate and their qualities
+    pub a: &'a SeqSlice<Dna>,
+    pub qa: &'a [u8],
+    /// The overlapping bases of the second mate and their qualities
+    pub b: &'a SeqSlice<Dna>,
+    pub qb: &'a [u8],
+    /// Number of positions at which `a` and `b` differ
+    pub mismatches: usize,
+}
+
+/// Objective function that scores a candidate overlap between two mates
+pub trait MatingObjective {
+    /// Score of the overlap, higher is better
+    fn score(&self, overlap: &Overlap, params: &MatingParams) -> f64;
+
+    /// Deducted from the score of an overlap `shift` bases away from the
+    /// expected overlap
+    fn shift_penalty(&self, shift: usize) -> f64 {
+        shift as f64
+    }
+
+    /// Whether the best overlap scores well enough to merge the mates
+    fn accept(&self, score: f64, params: &MatingParams) -> bool {
+        score >= params.min_score as f64
+    }
+}
+
+/// +1 for each match and `-mismatch_penalty` for each mismatch
+#[derive(Debug, Clone, Copy)]
+pub struct MismatchScore;
+
+impl MatingObjective for MismatchScore {
+    fn score(&self, overlap: &Overlap, params: &MatingParams) -> f64 {
+        let mismatches = overlap.mismatches;
+        (overlap.a.len() - mismatches) as f64 - (params.mismatch_penalty as f64 * mismatches as f64)
+    }
+}
+
+/// The negated fraction of mismatches, accepted up to `max_mismatch_rate`
+/// regardless of the overlap length
+#[derive(Debug, Clone, Copy)]
+pub struct HammingRate;
+
+impl MatingObjective for HammingRate {
+    fn score(&self, overlap: &Overlap, _params: &MatingParams) -> f64 {
+        if overlap.a.is_empty() {
+            return f64::NEG_INFINITY;
+        }
+        -(overlap.mismatches as f64 / overlap.a.len() as f64)
+    }
+
+    fn shift_penalty(&self, _shift: usize) -> f64 {
+        0.0
+    }
+
+    fn accept(&self, score: f64, params: &MatingParams) -> bool {
+        -score <= params.max_mismatch_rate
+    }
+}
+
+/// Log-likelihood ratio, in bits, of the mates sharing a template over the
+/// overlap against the overlap being unrelated sequence. Mismatches between
+/// low quality bases cost less than mismatches between high quality bases.
+#[derive(Debug, Clone, Copy)]
+pub struct LogLikelihood;
+
+impl MatingObjective for LogLikelihood {
+    fn score(&self, overlap: &Overlap, _params: &MatingParams) -> f64 {
+        let Overlap { a, qa, b, qb, .. } = *overlap;
+        let mut s = 0.0;
+        for i in 0..a.len() {
+            let (ea, eb) = (error_prob(qa[i]), error_prob(qb[i]));
+            // probability that both reads call the same base from one template
+            let same = (1.0 - ea) * (1.0 - eb) + ea * eb / 3.0;
+            s += if a[i] == b[i] {
+                (4.0 * same).log2()
+            } else {
+                ((1.0 - same) * 4.0 / 3.0).log2()
+            };
+        }
+        s
+    }
+}
+
+/// Mating objectives that can be chosen at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Objective {
+    #[default]
+    MismatchScore,
+    HammingRate,
+    LogLikelihood,
+}
+
+impl MatingObjective for Objective {
+    fn score(&self, overlap: &Overlap, params: &MatingParams) -> f64 {
+        match self {
+            Objective::MismatchScore => MismatchScore.score(overlap, params),
+            Objective::HammingRate => HammingRate.score(overlap, params),
+            Objective::LogLikelihood => LogLikelihood.score(overlap, params),
+        }
+    }
+
+    fn shift_penalty(&self, shift: usize) -> f64 {
+        match self {
+            Objective::MismatchScore => MismatchScore.shift_penalty(shift),
+            Objective::HammingRate => HammingRate.shift_penalty(shift),
+            Objective::LogLikelihood => LogLikelihood.shift_penalty(shift),
+        }
+    }
+
+    fn accept(&self, score: f64, params: &MatingParams) -> bool {
+        match self {
+            Objective::MismatchScore => MismatchScore.accept(score, params),
+            Objective::HammingRate => HammingRate.accept(score, params),
+            Objective::LogLikelihood => LogLikelihood.accept(score, params),
+        }
+    }
+}
+
+impl Objective {
+    /// Histogram bin of a mating: its score rounded down, or for `HammingRate`,
+    /// whose scores all lie between -1 and 0, its percentage of mismatches
+    pub fn bin(&self, mating: &Mating) -> i64 {
+        match (self, mating.len) {
+            (Objective::HammingRate, 0) => 100,
+            (Objective::HammingRate, len) => (100 * mating.mismatches / len) as i64,
+            _ => mating.score.floor() as i64,
+        }
+    }
+}
+
+impl fmt::Display for Objective {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Objective::MismatchScore => "score",
+            Objective::HammingRate => "hamming-rate",
+            Objective::LogLikelihood => "log-likelihood",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Objective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "score" => Ok(Objective::MismatchScore),
+            "hamming-rate" => Ok(Objective::HammingRate),
+            "log-likelihood" => Ok(Objective::LogLikelihood),
+            _ => Err(format!(
+                "unknown mating objective {}, expected score, hamming-rate or log-likelihood",
+                s
+            )),
+        }
+    }
+}
+
+/// Thresholds and scoring for accepting an overlap between mates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatingParams {
+    /// Shortest overlap that is accepted
+    pub min_overlap: usize,
+    /// Lowest overlap score that is accepted
+    pub min_score: i16,
+    /// Score deducted for each mismatch in the overlap, matches score 1
+    pub mismatch_penalty: i16,
+    /// Overlaps are searched up to this far from the expected overlap
+    pub max_span: usize,
+    /// Highest fraction of mismatches in an accepted overlap
+    pub max_mismatch_rate: f64,
+    /// How candidate overlaps are scored
+    pub objective: Objective,
+    /// Score deducted for each base of a gap in a gapped overlap
+    pub gap_penalty: i16,
+    /// Search for overlaps by gapped alignment, see `mate_gapped`
+    pub gapped: bool,
+}
+
+impl Default for MatingParams {
+    fn default() -> Self {
+        MatingParams {
+            min_overlap: 30,
+            min_score: 30,
+            mismatch_penalty: 1,
+            max_span: 84,
+            max_mismatch_rate: 0.25,
+            objective: Objective::MismatchScore,
+            gap_penalty: 2,
+            gapped: false,
+        }
+    }
+}
+
+/// Determine the index of overlap for two reads, see `mate_with`.
 #[inline]
-pub fn mate(r1: &SeqSlice<Dna>, r2: &SeqSlice<Dna>, hint: usize, indel: usize) -> Option<usize> {
-    let x = if hint > r1.len() {
-        0
-    } else {
-        (r1.len() - hint) * 2
-    };
+pub fn mate(
+    r1: &SeqSlice<Dna>,
+    r2: &SeqSlice<Dna>,
+    hint: usize,
+    params: &MatingParams,
+) -> Option<Mating> {
+    let q1 = vec![UNKNOWN_QUAL; r1.len()];
+    let q2 = vec![UNKNOWN_QUAL; r2.len()];
+    mate_qual(r1, &q1, r2, &q2, hint, params)
+}
+
+/// Determine the index of overlap for two reads with base qualities, using
+/// the objective chosen in `params`.
+#[inline]
+pub fn mate_qual(
+    r1: &SeqSlice<Dna>,
+    q1: &[u8],
+    r2: &SeqSlice<Dna>,
+    q2: &[u8],
+    hint: usize,
+    params: &MatingParams,
+) -> Option<Mating> {
+    mate_with(r1, q1, r2, q2, hint, params, &params.objective)
+}
 
-    if x >= r1.len() {
+/// Outcome of the search for the overlap of two mates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mating {
+    /// Index of overlap: the position in `r1` at which `r2` begins
+    pub index: usize,
+    /// Length of the best overlap
+    pub len: usize,
+    /// Score of the best overlap, less its shift penalty
+    pub score: f64,
+    /// Score of the next best overlap, if more than one was scored
+    pub runner_up: Option<f64>,
+    /// Mismatches in the best overlap
+    pub mismatches: usize,
+    /// Length of the best overlap less the length expected from the hint
+    pub offset: isize,
+    /// Whether the best overlap meets the thresholds to merge the mates
+    pub accepted: bool,
+}
+
+impl Mating {
+    /// Index of overlap, if the overlap was accepted
+    pub fn accepted_index(&self) -> Option<usize> {
+        self.accepted.then_some(self.index)
+    }
+}
+
+/// Overlap of two reads expected from `hint`, half the length of the
+/// fragment they were sequenced from. The reads may differ in length, eg.
+/// after quality trimming.
+#[inline]
+fn expected_overlap(r1: &SeqSlice<Dna>, r2: &SeqSlice<Dna>, hint: usize) -> usize {
+    (r1.len() + r2.len()).saturating_sub(hint * 2)
+}
+
+/// Determine the index of overlap for two reads.
+///
+/// The expected overlap is derived from `hint` and overlaps up to
+/// `params.max_span` bases shorter or longer are scored by `objective`.
+/// Reads of any lengths are accepted, as long as each has as many
+/// qualities as bases. Returns `None` if no overlap could be scored.
+pub fn mate_with<O: MatingObjective + ?Sized>(
+    r1: &SeqSlice<Dna>,
+    q1: &[u8],
+    r2: &SeqSlice<Dna>,
+    q2: &[u8],
+    hint: usize,
+    params: &MatingParams,
+    objective: &O,
+) -> Option<Mating> {
+    if q1.len() != r1.len() || q2.len() != r2.len() {
+        return None;
+    }
+
+    let x = expected_overlap(r1, r2, hint);
+    if x > r1.len() || x > r2.len() {
         return None;
     }
 
-    let mut m: i16 = score(&r2[..x], &r1[r1.len() - x..]);
-    let mut overlap: usize = r1.len() - x;
+    let (p1, p2) = (PackedSeq::new(r1), PackedSeq::new(r2));
 
-    for i in 1..indel {
+    // best (score, overlap length, mismatches, offset)
+    let mut best: Option<(f64, usize, usize, isize)> = None;
+    let mut runner_up: Option<f64> = None;
+    let mut consider = |l: usize, offset: isize| {
+        if l < params.min_overlap {
+            return;
+        }
+        let overlap = Overlap {
+            a: &r2[..l],
+            qa: &q2[..l],
+            b: &r1[r1.len() - l..],
+            qb: &q1[r1.len() - l..],
+            mismatches: p2.mismatches(0, &p1, r1.len() - l, l),
+        };
+        let s = objective.score(&overlap, params) - objective.shift_penalty(offset.unsigned_abs());
+        match best {
+            Some((m, ..)) if s <= m => {
+                if runner_up.map_or(true, |r| s > r) {
+                    runner_up = Some(s);
+                }
+            }
+            _ => {
+                runner_up = best.map(|(m, ..)| m);
+                best = Some((s, l, overlap.mismatches, offset));
+            }
+        }
+    };
+
+    consider(x, 0);
+    for i in 1..=params.max_span {
         if x > i {
             // search to the left
-            let l = x - i;
-            let hl = score(&r2[0..l], &r1[r1.len() - l..r1.len()]) - i as i16;
-            if hl > m {
-                m = hl;
-                overlap = r1.len() - l;
-            }
+            consider(x - i, -(i as isize));
         }
 
         // search to the right
         let r = x + i;
-        if r < r2.len() {
-            let hr = score(&r2[0..r], &r1[r1.len() - r..r1.len()]) - i as i16;
-            if hr > m {
-                m = hr;
-                overlap = r1.len() - r;
+        if r <= cmp::min(r1.len(), r2.len()) {
+            consider(r, i as isize);
+        }
+    }
+
+    let (score, len, mismatches, offset) = best?;
+    Some(Mating {
+        index: r1.len() - len,
+        len,
+        score,
+        runner_up,
+        mismatches,
+        offset,
+        accepted: objective.accept(score, params)
+            && mismatches as f64 <= params.max_mismatch_rate * len as f64,
+    })
+}
+
+/// Overlap between two mates found by gapped alignment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GappedOverlap {
+    /// Index of overlap: the position in `r1` at which `r2` begins
+    pub overlap: usize,
+    /// Number of bases of `r2` aligned to the end of `r1`
+    pub r2_len: usize,
+    /// Alignment of `r1[overlap..]` to `r2[..r2_len]`. `Del` is a base of `r1`
+    /// that is missing from `r2` and `Ins` a base of `r2` missing from `r1`.
+    pub ops: Vec<Cigar>,
+    pub score: i32,
+    /// Score of the best alignment from another index of overlap, if any
+    pub runner_up: Option<i32>,
+    /// Substitutions and indels in the alignment
+    pub edits: usize,
+    /// Whether the alignment meets the thresholds of `MatingParams`
+    pub accepted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Trace {
+    Start,
+    Diag,
+    Up,
+    Left,
+}
+
+/// Determine the overlap of two reads allowing for indels, by banded
+/// alignment of the end of `r1` against the start of `r2`.
+///
+/// Alignments start within `params.max_span` bases of the overlap expected
+/// from `hint` and stay within that band of its diagonal. Matches score 1,
+/// mismatches `-mismatch_penalty` and gaps `-gap_penalty` per base. The
+/// thresholds of `params` apply to the aligned part of `r1`, the best alignment
+/// is returned whether or not it meets them. Only the `MismatchScore`
+/// objective can score gapped overlaps, with any other objective no overlap is
+/// found.
+pub fn mate_gapped(
+    r1: &SeqSlice<Dna>,
+    r2: &SeqSlice<Dna>,
+    hint: usize,
+    params: &MatingParams,
+) -> Option<GappedOverlap> {
+    const NEG: i32 = i32::MIN / 2;
+
+    let (n, m) = (r1.len(), r2.len());
+    if n == 0 || m == 0 || params.objective != Objective::MismatchScore {
+        return None;
+    }
+    let s0 = n.saturating_sub(expected_overlap(r1, r2, hint));
+    let band = params.max_span;
+    let mismatch = params.mismatch_penalty as i32;
+    let gap = params.gap_penalty as i32;
+
+    // only the band is stored: row i holds the columns within `band` of the
+    // diagonal `i - s0`, ie. j - (i - s0) + band in 0..width
+    let width = 2 * band + 1;
+    let at = |i: usize, j: usize| -> Option<usize> {
+        let k = (j + s0 + band).checked_sub(i)?;
+        (k < width).then_some(i * width + k)
+    };
+    let mut h = vec![NEG; (n + 1) * width];
+    let mut trace = vec![Trace::Start; (n + 1) * width];
+    // row of r1 at which the alignment through each cell begins
+    let mut origin = vec![0; (n + 1) * width];
+    let get = |h: &[i32], i: usize, j: usize| at(i, j).map_or(NEG, |cell| h[cell]);
+    let from = |origin: &[usize], i: usize, j: usize| at(i, j).map_or(0, |cell| origin[cell]);
+
+    // the overlap may begin anywhere in r1 within the band
+    for i in 0..=n {
+        if let Some(cell) = at(i, 0) {
+            h[cell] = 0;
+            origin[cell] = i;
+        }
+    }
+
+    for i in 1..=n {
+        let d = i as isize - s0 as isize;
+        let lo = cmp::max(1, d - band as isize);
+        let hi = cmp::min(m as isize, d + band as isize);
+        if hi < lo {
+            continue;
+        }
+        for j in lo as usize..=hi as usize {
+            let Some(cell) = at(i, j) else { continue };
+            let sub = if r1[i - 1] == r2[j - 1] { 1 } else { -mismatch };
+            let diag = get(&h, i - 1, j - 1) + sub;
+            let up = get(&h, i - 1, j) - gap;
+            let left = get(&h, i, j - 1) - gap;
+
+            if diag >= up && diag >= left {
+                h[cell] = diag;
+                trace[cell] = Trace::Diag;
+                origin[cell] = from(&origin, i - 1, j - 1);
+            } else if up >= left {
+                h[cell] = up;
+                trace[cell] = Trace::Up;
+                origin[cell] = from(&origin, i - 1, j);
+            } else {
+                h[cell] = left;
+                trace[cell] = Trace::Left;
+                origin[cell] = from(&origin, i, j - 1);
+            }
+        }
+    }
+
+    // r1 is aligned to its end, r2 may continue past it
+    let mut best = 1;
+    for j in 2..=m {
+        if get(&h, n, j) > get(&h, n, best) {
+            best = j;
+        }
+    }
+    let score = get(&h, n, best);
+    if score <= NEG / 2 {
+        return None;
+    }
+    let runner_up = (1..=m)
+        .filter(|&j| from(&origin, n, j) != from(&origin, n, best))
+        .map(|j| get(&h, n, j))
+        .filter(|&s| s > NEG / 2)
+        .max();
+
+    let (mut i, mut j) = (n, best);
+    let mut ops = Vec::new();
+    let mut edits = 0;
+    while j > 0 {
+        match at(i, j).map_or(Trace::Start, |cell| trace[cell]) {
+            Trace::Diag => {
+                if r1[i - 1] == r2[j - 1] {
+                    ops.push(Cigar::Match);
+                } else {
+                    ops.push(Cigar::Subs);
+                    edits += 1;
+                }
+                i -= 1;
+                j -= 1;
             }
+            Trace::Up => {
+                ops.push(Cigar::Del);
+                edits += 1;
+                i -= 1;
+            }
+            Trace::Left => {
+                ops.push(Cigar::Ins);
+                edits += 1;
+                j -= 1;
+            }
+            Trace::Start => break,
         }
     }
-    if m >= 30 {
-        return Some(overlap);
+    ops.reverse();
+
+    let len = n - i;
+    let accepted = len >= params.min_overlap
+        && score >= params.min_score as i32
+        && edits as f64 <= params.max_mismatch_rate * len as f64;
+
+    Some(GappedOverlap {
+        overlap: i,
+        r2_len: best,
+        ops,
+        score,
+        runner_up,
+        edits,
+        accepted,
+    })
+}
+
+/// Mate two reads in the same orientation and merge them, by gapped
+/// alignment if `params.gapped` is set. Returns the outcome of mating, if
+/// any overlap could be scored, and the merged read if it was accepted.
+pub fn mate_and_merge(
+    r1: &SeqSlice<Dna>,
+    q1: &[u8],
+    r2: &SeqSlice<Dna>,
+    q2: &[u8],
+    hint: usize,
+    params: &MatingParams,
+) -> (Option<Mating>, Option<(Seq<Dna>, Vec<u8>)>) {
+    if q1.len() != r1.len() || q2.len() != r2.len() {
+        return (None, None);
+    }
+    if params.gapped {
+        match mate_gapped(r1, r2, hint, params) {
+            Some(overlap) => {
+                let len = r1.len() - overlap.overlap;
+                let mating = Mating {
+                    index: overlap.overlap,
+                    len,
+                    score: overlap.score as f64,
+                    runner_up: overlap.runner_up.map(f64::from),
+                    mismatches: overlap.edits,
+                    offset: len as isize - expected_overlap(r1, r2, hint) as isize,
+                    accepted: overlap.accepted,
+                };
+                let merged = overlap
+                    .accepted
+                    .then(|| merge_gapped(r1, q1, r2, q2, &overlap));
+                (Some(mating), merged)
+            }
+            None => (None, None),
+        }
+    } else {
+        let mating = mate_qual(r1, q1, r2, q2, hint, params);
+        let merged = mating
+            .and_then(|m| m.accepted_index())
+            .and_then(|index| merge_qual(r1, q1, r2, q2, index));
+        (mating, merged)
+    }
+}
+
+/// Find the shortest insert length `l` within `lengths` for which the first `l`
+/// bases of `r1` are the reverse complement of the first `l` bases of `r2`.
+///
+/// Inserts this short are sequenced in full by both mates, which then read on
+/// into the adapter.
+pub fn short_insert(
+    r1: &SeqSlice<Dna>,
+    r2: &SeqSlice<Dna>,
+    lengths: RangeInclusive<usize>,
+    max_mismatches: usize,
+) -> Option<usize> {
+    let longest = cmp::min(*lengths.end(), cmp::min(r1.len(), r2.len()));
+    // the reverse complement of r2[..l] ends the reverse complement of r2
+    let (p1, p2rc) = (PackedSeq::new(r1), PackedSeq::new(&r2.revcomp()));
+    for l in *lengths.start()..=longest {
+        if p1.mismatches(0, &p2rc, r2.len() - l, l) <= max_mismatches {
+            return Some(l);
+        }
     }
     None
 }
 
-/// Mating with the Hamming rate objective function. Complexity: O(n^2).
-#[allow(dead_code)]
+/// Length of the insert if both mates read through it into the adapter, ie.
+/// the insert is no longer than the shorter read and each read starts with
+/// the reverse complement of the other's insert bases.
+///
+/// `mate` cannot find these overlaps: the overlap is the whole insert and the
+/// bases that follow it in each read are adapter, not the other read's start.
+pub fn read_through(
+    r1: &SeqSlice<Dna>,
+    r2: &SeqSlice<Dna>,
+    min_insert: usize,
+    max_mismatches: usize,
+) -> Option<usize> {
+    let longest = cmp::min(r1.len(), r2.len());
+    if min_insert > longest {
+        return None;
+    }
+    short_insert(r1, r2, min_insert..=longest, max_mismatches)
+}
+
+/// Trim the adapter from a pair of reads that read through an insert of
+/// length `insert` and reconcile the mates over the whole insert. The insert
+/// is returned in the orientation of `r1`.
+pub fn merge_read_through(
+    r1: &SeqSlice<Dna>,
+    q1: &[u8],
+    r2: &SeqSlice<Dna>,
+    q2: &[u8],
+    insert: usize,
+) -> (Seq<Dna>, Vec<u8>) {
+    let r2rc = r2[..insert].revcomp();
+    let q2rc: Vec<u8> = q2[..insert].iter().rev().copied().collect();
+    merge_overlap(&r1[..insert], &q1[..insert], &r2rc, &q2rc, 0)
+}
+
+/// Mating with the Hamming rate objective function, trying every overlap of
+/// at least `min_overlap` bases. Returns the index of overlap of the lowest
+/// mismatch rate, preferring longer overlaps. Complexity: O(n^2).
 pub fn mate_hamming_rate(
     r1: &SeqSlice<Dna>,
     r2: &SeqSlice<Dna>,
-    overlap_bound: usize,
-    min_score: i16,
+    min_overlap: usize,
+    max_rate: f64,
 ) -> Option<usize> {
     let max_overlap = cmp::min(r1.len(), r2.len());
-    let min_overlap = overlap_bound - 1;
-    let mut m: i16 = 0;
-    let mut overlap: usize = 0;
-    for i in min_overlap..max_overlap {
-        //        let h = hamming(&r2[0..i], &r1[r1.len()-i..r1.len()]) / i;
-        let h = 0;
-        if h > m {
-            m = h;
-            overlap = i;
+    let (p1, p2) = (PackedSeq::new(r1), PackedSeq::new(r2));
+    let mut best: Option<(f64, usize)> = None;
+    for l in cmp::max(min_overlap, 1)..=max_overlap {
+        let h = p2.mismatches(0, &p1, r1.len() - l, l) as f64 / l as f64;
+        if best.map_or(true, |(m, _)| h <= m) {
+            best = Some((h, l));
         }
     }
-    if m >= min_score {
-        return Some(overlap);
+    match best {
+        Some((m, l)) if m <= max_rate => Some(r1.len() - l),
+        _ => None,
     }
-    None
 }
 
-/// Mating objective function that penalizes mismatches.
+/// Highest phred quality assigned to a merged base
+pub const MAX_QUAL: u8 = 41;
+
 #[inline]
-fn score(r1: &SeqSlice<Dna>, r2: &SeqSlice<Dna>) -> i16 {
-    let mut s: i16 = 0;
-    let len = r1.len();
-    for i in 0..len {
-        if r1[i] == r2[i] {
-            s += 1;
+fn error_prob(q: u8) -> f64 {
+    10f64.powf(-(q as f64) / 10.0)
+}
+
+#[inline]
+fn phred(p: f64) -> u8 {
+    (-10.0 * p.log10()).round().clamp(0.0, MAX_QUAL as f64) as u8
+}
+
+/// Posterior base call and phred quality for two observations of the same
+/// position, assuming sequencing errors are independent and equally likely
+/// to produce any of the other three bases.
+#[inline]
+pub fn consensus_base(a: Dna, qa: u8, b: Dna, qb: u8) -> (Dna, u8) {
+    let (ea, eb) = (error_prob(qa), error_prob(qb));
+    if a == b {
+        let p = (ea * eb / 3.0) / (1.0 - ea - eb + 4.0 * ea * eb / 3.0);
+        (a, phred(p))
+    } else {
+        let (base, e1, e2) = if qa >= qb { (a, ea, eb) } else { (b, eb, ea) };
+        let p = e1 * (1.0 - e2 / 3.0) / (e1 + e2 - 4.0 * e1 * e2 / 3.0);
+        (base, phred(p))
+    }
+}
+
+/// Merge two reads and their phred qualities, reconciling the overlap with
+/// `consensus_base`. Returns the merged sequence and its posterior qualities,
+/// or `None` if the index of overlap lies past the end of `r1`, leaving a gap
+/// between the reads. Each read must have as many qualities as bases.
+pub fn merge_qual(
+    r1: &SeqSlice<Dna>,
+    q1: &[u8],
+    r2: &SeqSlice<Dna>,
+    q2: &[u8],
+    overlap: usize,
+) -> Option<(Seq<Dna>, Vec<u8>)> {
+    if overlap > r1.len() {
+        return None;
+    }
+    Some(merge_overlap(r1, q1, r2, q2, overlap))
+}
+
+/// `merge_qual` for an index of overlap within `r1`
+fn merge_overlap(
+    r1: &SeqSlice<Dna>,
+    q1: &[u8],
+    r2: &SeqSlice<Dna>,
+    q2: &[u8],
+    overlap: usize,
+) -> (Seq<Dna>, Vec<u8>) {
+    let mut seq: Vec<Dna> = Vec::with_capacity(overlap + r2.len());
+    let mut qual: Vec<u8> = Vec::with_capacity(overlap + r2.len());
+
+    for i in 0..r1.len() {
+        if i >= overlap && i - overlap < r2.len() {
+            let (base, q) = consensus_base(r1[i], q1[i], r2[i - overlap], q2[i - overlap]);
+            seq.push(base);
+            qual.push(q);
         } else {
-            s -= 1;
+            seq.push(r1[i]);
+            qual.push(q1[i]);
         }
     }
-    s
+    for j in r1.len() - overlap..r2.len() {
+        seq.push(r2[j]);
+        qual.push(q2[j]);
+    }
+
+    (seq.into_iter().collect(), qual)
+}
+
+/// Merge two reads along a gapped overlap. Aligned bases are reconciled
+/// with `consensus_base`; a base present in only one read is kept if its
+/// quality is at least that of the other read's base beside the gap.
+pub fn merge_gapped(
+    r1: &SeqSlice<Dna>,
+    q1: &[u8],
+    r2: &SeqSlice<Dna>,
+    q2: &[u8],
+    overlap: &GappedOverlap,
+) -> (Seq<Dna>, Vec<u8>) {
+    let mut seq: Vec<Dna> = Vec::with_capacity(overlap.overlap + r2.len());
+    let mut qual: Vec<u8> = Vec::with_capacity(overlap.overlap + r2.len());
+
+    for i in 0..overlap.overlap {
+        seq.push(r1[i]);
+        qual.push(q1[i]);
+    }
+
+    // quality of the base beside a gap in the other read
+    let beside = |q: &[u8], k: usize| -> u8 {
+        match (k.checked_sub(1).and_then(|k| q.get(k)), q.get(k)) {
+            (Some(a), Some(b)) => cmp::min(*a, *b),
+            (Some(a), None) | (None, Some(a)) => *a,
+            (None, None) => 0,
+        }
+    };
+
+    let (mut i, mut j) = (overlap.overlap, 0);
+    for op in &overlap.ops {
+        match op {
+            Cigar::Match | Cigar::Subs => {
+                let (base, q) = consensus_base(r1[i], q1[i], r2[j], q2[j]);
+                seq.push(base);
+                qual.push(q);
+                i += 1;
+                j += 1;
+            }
+            Cigar::Del => {
+                if q1[i] >= beside(q2, j) {
+                    seq.push(r1[i]);
+                    qual.push(q1[i]);
+                }
+                i += 1;
+            }
+            Cigar::Ins => {
+                if q2[j] >= beside(q1, i) {
+                    seq.push(r2[j]);
+                    qual.push(q2[j]);
+                }
+                j += 1;
+            }
+        }
+    }
+
+    for j in overlap.r2_len..r2.len() {
+        seq.push(r2[j]);
+        qual.push(q2[j]);
+    }
+
+    (seq.into_iter().collect(), qual)
+}
+
+/// Replace bases with a quality below `min_qual` by `N`
+pub fn mask(seq: &SeqSlice<Dna>, qual: &[u8], min_qual: u8) -> Seq<Iupac> {
+    seq.into_iter()
+        .zip(qual)
+        .map(|(base, q)| match base {
+            _ if *q < min_qual => Iupac::N,
+            Dna::A => Iupac::A,
+            Dna::C => Iupac::C,
+            Dna::G => Iupac::G,
+            Dna::T => Iupac::T,
+        })
+        .collect()
 }
 
 /// Function that replaces disagreeing reads with 'N'.
@@ -121,9 +843,10 @@ pub fn mend_consensus(a: u8, b: u8) -> u8 {
 }
 */
 
-/// Given two reads and the index of overlap, merge them together.
+/// Given two reads and the index of overlap, merge them together. Returns
+/// `None` if the index lies past the end of `r1`.
 #[inline]
-pub fn merge(r1: &SeqSlice<Dna>, r2: &SeqSlice<Dna>, overlap: usize) -> Seq<Dna> {
+pub fn merge(r1: &SeqSlice<Dna>, r2: &SeqSlice<Dna>, overlap: usize) -> Option<Seq<Dna>> {
     //let len = overlap + r2.len();
 
     //let mut seq: Vec<Dna> = vec![Dna::A; len];
@@ -132,9 +855,12 @@ pub fn merge(r1: &SeqSlice<Dna>, r2: &SeqSlice<Dna>, overlap: usize) -> Seq<Dna>
     //let mut seq: Seq<Dna> = Seq::new();
     // decide what to do for the overlapping par
 
-    r1.into_iter()
-        .chain(&r2[r1.len() - overlap..r2.len()])
-        .collect()
+    if overlap > r1.len() {
+        return None;
+    }
+    // r2 may end within r1
+    let tail = cmp::min(r1.len() - overlap, r2.len());
+    Some(r1.into_iter().chain(&r2[tail..]).collect())
     //for i in overlap..r1.len() {
     //        seq[i] = mend_consensus(r1[i], r2[i - overlap]);
     //    seq[i] = r1[i];
@@ -162,76 +888,346 @@ pub fn truncate(
     Seq::from(&seq)
 }
 */
-/*
 #[cfg(test)]
 mod tests {
-    use super::{mate, mend_consensus, merge, truncate};
+    use super::{
+        consensus_base, mask, mate, mate_and_merge, mate_gapped, mate_hamming_rate, mate_qual,
+        merge, merge_qual, merge_read_through, read_through, Mating, MatingParams, Objective,
+        MAX_QUAL,
+    };
+    use crate::aligner::Cigar;
+    use bio_seq::prelude::*;
+
+    fn params(min_overlap: usize, min_score: i16, max_span: usize) -> MatingParams {
+        MatingParams {
+            min_overlap,
+            min_score,
+            max_span,
+            ..Default::default()
+        }
+    }
+
+    fn seq(s: &str) -> Seq<Dna> {
+        s.try_into().unwrap()
+    }
+
+    /// Index of overlap of an accepted mating
+    fn accepted(mating: Option<Mating>) -> Option<usize> {
+        mating.and_then(|m| m.accepted_index())
+    }
 
     #[test]
     fn test_mate_pair() {
-        let r1 = b"tacgattcgat";
-        let r2 = b"ttcgattacgt";
-        let offset = mate(r1, r2, 3, 3).unwrap();
-        assert_eq!(offset, 6);
+        let r1 = seq("TACGATTCGAT");
+        let r2 = seq("TTCGATTACGT");
+        assert_eq!(accepted(mate(&r1, &r2, 8, &params(3, 3, 3))), Some(5));
+        // an imprecise hint is corrected at the cost of the shift
+        assert_eq!(accepted(mate(&r1, &r2, 9, &params(3, 3, 3))), Some(5));
+        assert_eq!(accepted(mate(&r1, &r2, 9, &params(3, 5, 3))), None);
+        // the search reaches max_span bases either side
+        assert_eq!(accepted(mate(&r1, &r2, 9, &params(3, 3, 2))), Some(5));
+    }
+
+    #[test]
+    fn test_mating_diagnostics() {
+        let r1 = seq("TACGATTCGAT");
+        let r2 = seq("TTCGATTACGT");
+        let mating = mate(&r1, &r2, 8, &params(3, 3, 3)).unwrap();
+        assert_eq!(
+            mating,
+            Mating {
+                index: 5,
+                len: 6,
+                score: 6.0,
+                runner_up: Some(-4.0),
+                mismatches: 0,
+                offset: 0,
+                accepted: true,
+            }
+        );
+
+        // found two bases further than expected, scoring too low after the shift
+        let mating = mate(&r1, &r2, 9, &params(3, 5, 3)).unwrap();
+        assert!(!mating.accepted);
+        assert_eq!(mating.accepted_index(), None);
+        assert_eq!((mating.index, mating.offset, mating.score), (5, 2, 4.0));
+        assert_eq!(mating.runner_up, Some(-4.0));
+
+        // nothing to score
+        assert_eq!(mate(&r1[..2], &r2, 8, &params(3, 3, 3)), None);
     }
 
     #[test]
     fn test_total_overlap() {
-        let r1 = b"asdfghjkl";
-        let r2 = b"asdfghjkl";
-        let offset = mate(r1, r2, 3, 3).unwrap();
-        assert_eq!(offset, 9);
+        let r = seq("CATGCATGCCGA");
+        assert_eq!(accepted(mate(&r, &r, 6, &params(3, 3, 3))), Some(0));
     }
 
     #[test]
     fn test_merge_pair() {
-        let r1 = b"tacgattcgat";
-        let r2 = b"ttcgattacgt";
-        assert_eq!(merge(r1, r2, 6, mend_consensus), b"tacgattcgattacgt");
+        let r1 = seq("TACGATTCGAT");
+        let r2 = seq("TTCGATTACGT");
+        assert_eq!(merge(&r1, &r2, 5).unwrap().to_string(), "TACGATTCGATTACGT");
     }
 
     #[test]
     fn test_merge_consensus() {
-        let r1 = b"actgtagtacaccatgatg";
-        let r2 = b"gtttaccatgatggattga";
-        let offset = mate(r1, r2, 3, 3).unwrap();
-        assert_eq!(offset, 13);
+        let r1 = seq("ACTGTAGTACACCATGATG");
+        let r2 = seq("GTTTACCATGATGGATTGA");
+        let offset = accepted(mate(&r1, &r2, 13, &params(3, 3, 3))).unwrap();
+        assert_eq!(offset, 6);
+        let (merged, qual) = merge_qual(&r1, &[30; 19], &r2, &[30; 19], offset).unwrap();
         assert_eq!(
-            merge(r1, r2, offset, mend_consensus),
-            b"actgtagtNNaccatgatggattga"
+            mask(&merged, &qual, 10).to_string(),
+            "ACTGTAGTNNACCATGATGGATTGA"
         );
     }
 
     #[test]
     fn test_overlap_bounds() {
-        let r1 = b"ctagtcagctagcatgcatgctcgcgtatacgctagc";
-        let r2 = b"gtatacgctagccgctagcatgcat";
-        assert_eq!(mate(r1, r2, 12, 12).unwrap(), 12);
-        assert_eq!(mate(r1, r2, 13, 13), None);
+        let r1 = seq("ACTGTAGTACACCATGATG");
+        let r2 = seq("GTTTACCATGATGGATTGA");
+        assert_eq!(accepted(mate(&r1, &r2, 13, &params(13, 3, 3))), Some(6));
+        assert_eq!(accepted(mate(&r1, &r2, 13, &params(14, 3, 3))), None);
     }
 
     #[test]
     fn test_score_bounds() {
-        let r1 = b"there is no restriction to DNA strings";
-        let r2 = b"ctiin ot dNA atrings asdf qwer";
-        assert_eq!(mate(r1, r2, 12, 10).unwrap(), 20);
-        assert_eq!(mate(r1, r2, 12, 11), None);
+        let r1 = seq("ACTGTAGTACACCATGATG");
+        let r2 = seq("GTTTACCATGATGGATTGA");
+        // 11 matches, 2 mismatches and a shift of 1
+        assert_eq!(accepted(mate(&r1, &r2, 13, &params(3, 8, 3))), Some(6));
+        assert_eq!(accepted(mate(&r1, &r2, 13, &params(3, 9, 3))), None);
+
+        let strict = MatingParams {
+            mismatch_penalty: 3,
+            ..params(3, 5, 3)
+        };
+        assert_eq!(accepted(mate(&r1, &r2, 13, &strict)), None);
+
+        let max_rate = |max_mismatch_rate| MatingParams {
+            max_mismatch_rate,
+            ..params(3, 3, 3)
+        };
+        assert_eq!(accepted(mate(&r1, &r2, 13, &max_rate(0.2))), Some(6));
+        assert_eq!(accepted(mate(&r1, &r2, 13, &max_rate(0.1))), None);
     }
 
     #[test]
-    fn test_truncation() {
-        let r1 = b"cgctgtcatgc";
-        let r2 = b"tcatgccgctgt";
-        assert_eq!(truncate(r1, r2, 6, mend_consensus), b"cgctgt");
+    fn test_disjoint() {
+        let r1 = seq("ACGTACGTAC");
+        let r2 = seq("TTTTGGGGCC");
+        assert_eq!(accepted(mate(&r1, &r2, 5, &params(3, 3, 5))), None);
     }
 
     #[test]
-    fn test_overlap() {}
+    fn test_asymmetric_mates() {
+        let template = "CCATCAGACGAGCTAAGGTCCAAGGGCTAAAAATAGATGGTTCGGTAGTTAATGATTACC";
+        let t = seq(template);
+        let p = params(10, 10, 5);
+
+        // r2 quality trimmed to 35bp
+        let (r1, r2) = (&t[..40], &t[25..]);
+        assert_eq!(accepted(mate(r1, r2, 29, &p)), Some(25));
+        assert_eq!(merge(r1, r2, 25).unwrap().to_string(), template);
+
+        // r1 trimmed to 20bp, shorter than the hint
+        let (r1, r2) = (&t[..20], &t[5..]);
+        assert_eq!(accepted(mate(r1, r2, 29, &p)), Some(5));
+        let (merged, qual) = merge_qual(r1, &[30; 20], r2, &[30; 55], 5).unwrap();
+        assert_eq!(merged.to_string(), template);
+        assert_eq!(qual.len(), 60);
+
+        // shorter than the minimum overlap
+        assert_eq!(accepted(mate(&t[..8], &t[5..], 29, &p)), None);
+        assert_eq!(accepted(mate(&t[..0], &t[5..], 29, &p)), None);
+        // qualities that do not match the reads
+        assert_eq!(
+            accepted(mate_qual(&t[..40], &[30; 39], &t[25..], &[30; 35], 29, &p)),
+            None
+        );
+
+        // r2 ends within r1, and an index of overlap past the end of r1
+        // leaves a gap that cannot be merged
+        assert_eq!(
+            merge(&t[..40], &t[10..20], 10).unwrap().to_string(),
+            &template[..40]
+        );
+        assert_eq!(merge(&t[..20], &t[40..], 20).unwrap().to_string().len(), 40);
+        assert_eq!(merge(&t[..20], &t[40..], 30), None);
+        assert_eq!(
+            merge_qual(&t[..20], &[30; 20], &t[40..], &[30; 20], 30),
+            None
+        );
+    }
 
     #[test]
-    fn test_readthrough() {}
+    fn test_consensus_base() {
+        assert_eq!(consensus_base(Dna::A, 30, Dna::A, 30), (Dna::A, MAX_QUAL));
+        assert_eq!(consensus_base(Dna::A, 10, Dna::A, 10), (Dna::A, 24));
+        assert_eq!(consensus_base(Dna::A, 30, Dna::C, 10), (Dna::A, 20));
+        assert_eq!(consensus_base(Dna::A, 5, Dna::T, 35), (Dna::T, 30));
+        // equally good disagreeing bases are close to a coin toss
+        assert_eq!(consensus_base(Dna::G, 20, Dna::C, 20), (Dna::G, 3));
+    }
 
     #[test]
-    fn test_disjoint() {}
+    fn test_merge_qual() {
+        let r1: Seq<Dna> = "AAAACCCCGG".try_into().unwrap();
+        let q1 = [30, 30, 30, 30, 30, 30, 30, 30, 30, 5];
+        let r2: Seq<Dna> = "CCATTTTT".try_into().unwrap();
+        let q2 = [10, 10, 10, 35, 30, 30, 30, 30];
+
+        let (seq, qual) = merge_qual(&r1, &q1, &r2, &q2, 6).unwrap();
+        assert_eq!(seq.to_string(), "AAAACCCCGTTTTT");
+        assert_eq!(
+            qual,
+            vec![30, 30, 30, 30, 30, 30, 41, 41, 20, 30, 30, 30, 30, 30]
+        );
+        assert_eq!(mask(&seq, &qual, 25).to_string(), "AAAACCCCNTTTTT");
+    }
+
+    #[test]
+    fn test_readthrough() {
+        // a 40bp insert sequenced with 60bp reads runs 20bp into the adapters
+        let insert = "TTGACCGATAGCTAGGCATCGATCGGATACGTAGCTAGCC";
+        let r1: Seq<Dna> = "TTGACCGATAGCTAGGCATCGATCGGATACGTAGCTAGCCAGATCGGAAGAGCACACGTC"
+            .try_into()
+            .unwrap();
+        let r2: Seq<Dna> = "GGCTAGCTACGTATCCGATCGATGCCTAGCTATCGGTCAAAGATCGGAAGAGCGTCGTGT"
+            .try_into()
+            .unwrap();
+        let q = [30; 60];
+
+        assert_eq!(read_through(&r1, &r2, 20, 2), Some(40));
+        let (seq, qual) = merge_read_through(&r1, &q, &r2, &q, 40);
+        assert_eq!(seq.to_string(), insert);
+        assert_eq!(qual, vec![MAX_QUAL; 40]);
+
+        // a read 5bp into the insert shortens it
+        assert_eq!(read_through(&r1[5..], &r2, 20, 2), Some(35));
+        // mates that are not each other's reverse complement do not read through
+        assert_eq!(read_through(&r1, &r1, 20, 2), None);
+        assert_eq!(read_through(&r1, &r2, 61, 2), None);
+    }
+
+    #[test]
+    fn test_objectives() {
+        let r1 = seq("ACTGTAGTACACCATGATG");
+        let r2 = seq("GTTTACCATGATGGATTGA");
+        let with = |objective, max_mismatch_rate, min_score| MatingParams {
+            objective,
+            max_mismatch_rate,
+            min_score,
+            ..params(3, 3, 3)
+        };
+
+        let score = with(Objective::MismatchScore, 0.25, 3);
+        assert_eq!(accepted(mate(&r1, &r2, 13, &score)), Some(6));
+
+        // 2 mismatches in 13 bases
+        let rate = |max_rate| with(Objective::HammingRate, max_rate, 0);
+        assert_eq!(accepted(mate(&r1, &r2, 13, &rate(0.2))), Some(6));
+        assert_eq!(accepted(mate(&r1, &r2, 13, &rate(0.15))), None);
+
+        // mismatches between high quality bases are strong evidence against an overlap
+        let ll = |min_score| with(Objective::LogLikelihood, 0.25, min_score);
+        let q = |q: u8| [q; 19];
+        assert_eq!(
+            accepted(mate_qual(&r1, &q(30), &r2, &q(30), 13, &ll(3))),
+            Some(6)
+        );
+        assert_eq!(
+            accepted(mate_qual(&r1, &q(40), &r2, &q(40), 13, &ll(3))),
+            None
+        );
+        assert_eq!(
+            accepted(mate_qual(&r1, &q(10), &r2, &q(10), 13, &ll(10))),
+            Some(6)
+        );
+    }
+
+    #[test]
+    fn test_objective_names() {
+        for objective in [
+            Objective::MismatchScore,
+            Objective::HammingRate,
+            Objective::LogLikelihood,
+        ] {
+            assert_eq!(objective.to_string().parse(), Ok(objective));
+        }
+        assert!("hamming".parse::<Objective>().is_err());
+    }
+
+    #[test]
+    fn test_mate_hamming_rate() {
+        let r1 = seq("TACGATTCGAT");
+        let r2 = seq("TTCGATTACGT");
+        assert_eq!(mate_hamming_rate(&r1, &r2, 3, 0.0), Some(5));
+
+        let r1 = seq("ACTGTAGTACACCATGATG");
+        let r2 = seq("GTTTACCATGATGGATTGA");
+        assert_eq!(mate_hamming_rate(&r1, &r2, 5, 0.2), Some(6));
+        assert_eq!(mate_hamming_rate(&r1, &r2, 5, 0.1), None);
+    }
+
+    #[test]
+    fn test_gapped_overlap() {
+        // r2 is missing one base of the AAAAA homopolymer in the overlap
+        let template = "CCATCAGACGAGCTAAGGTCCAAGGGCTAAAAATAGATGGTTCGGTAGTTAATGATTACC";
+        let r1 = seq("CCATCAGACGAGCTAAGGTCCAAGGGCTAAAAATAGATGG");
+        let r2 = seq("CAAGGGCTAAAATAGATGGTTCGGTAGTTAATGATTACC");
+        let ungapped = params(10, 10, 5);
+        let gapped = MatingParams {
+            gapped: true,
+            ..ungapped
+        };
+
+        // the indel shifts the diagonal halfway through the overlap
+        assert_eq!(accepted(mate(&r1, &r2, 30, &ungapped)), None);
+
+        let overlap = mate_gapped(&r1, &r2, 30, &gapped).unwrap();
+        assert!(overlap.accepted);
+        assert_eq!(overlap.overlap, 20);
+        assert_eq!(overlap.r2_len, 19);
+        assert_eq!(overlap.edits, 1);
+        assert_eq!(overlap.score, 17);
+        assert!(overlap.runner_up.unwrap() < overlap.score);
+        assert_eq!(
+            overlap.ops.iter().filter(|op| **op == Cigar::Del).count(),
+            1
+        );
+
+        // scores of other objectives are not comparable to alignment scores
+        let likelihood = MatingParams {
+            objective: Objective::LogLikelihood,
+            ..gapped
+        };
+        assert!(mate_gapped(&r1, &r2, 30, &likelihood).is_none());
+
+        // a rejected overlap is still reported, without a merged read
+        let strict = MatingParams {
+            min_score: 18,
+            ..gapped
+        };
+        let (mating, merged) = mate_and_merge(&r1, &[30; 40], &r2, &[20; 39], 30, &strict);
+        let mating = mating.unwrap();
+        assert!(!mating.accepted);
+        assert_eq!((mating.index, mating.score, mating.offset), (20, 17.0, 1));
+        assert!(mating.runner_up.is_some());
+        assert_eq!(merged, None);
+
+        // the better supported read decides the length of the homopolymer
+        let (merged, _) = mate_and_merge(&r1, &[30; 40], &r2, &[20; 39], 30, &gapped)
+            .1
+            .unwrap();
+        assert_eq!(merged.to_string(), template);
+        let (merged, _) = mate_and_merge(&r1, &[20; 40], &r2, &[30; 39], 30, &gapped)
+            .1
+            .unwrap();
+        assert_eq!(
+            merged.to_string(),
+            "CCATCAGACGAGCTAAGGTCCAAGGGCTAAAATAGATGGTTCGGTAGTTAATGATTACC"
+        );
+    }
 }
-*/
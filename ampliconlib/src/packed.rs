@@ -0,0 +1,145 @@
+//! 2-bit packed reads for comparing overlaps a word at a time
+//!
+//! A read is packed once, 32 bases to a `u64` in read order. Two stretches of
+//! reads are then compared by XOR: a base differs iff either of its two bits
+//! differs, so folding the high bit of each base onto the low bit and counting
+//! the set bits gives the number of mismatches in 32 bases at once. Stretches
+//! need not be word aligned, each window is funnel shifted out of two words.
+
+use bio_seq::prelude::*;
+
+/// Bases per packed word
+const BASES: usize = 32;
+
+/// The low bit of every base in a word
+const LOW_BITS: u64 = 0x5555_5555_5555_5555;
+
+/// Bases loaded at a time from a `SeqSlice`
+const CHUNK: usize = usize::BITS as usize / 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedSeq {
+    /// Padded with an empty word so that a window can always read the word
+    /// after the one it starts in
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl PackedSeq {
+    /// bio_seq already packs `Dna` two bits to a base with the first base in
+    /// the low bits, so the words are loaded out of its storage a chunk at a
+    /// time rather than base by base
+    pub fn new(seq: &SeqSlice<Dna>) -> Self {
+        let mut words = vec![0; seq.len().div_ceil(BASES) + 1];
+        for i in (0..seq.len()).step_by(CHUNK) {
+            let chunk = usize::from(&seq[i..std::cmp::min(i + CHUNK, seq.len())]);
+            words[i / BASES] |= (chunk as u64) << (2 * (i % BASES));
+        }
+        PackedSeq {
+            words,
+            len: seq.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The 32 bases starting at base `i`, past the end of the read are As
+    #[inline]
+    fn window(&self, i: usize) -> u64 {
+        let (w, shift) = (i / BASES, 2 * (i % BASES));
+        if shift == 0 {
+            self.words[w]
+        } else {
+            (self.words[w] >> shift) | (self.words[w + 1] << (64 - shift))
+        }
+    }
+
+    /// Number of positions at which `len` bases from `i` differ from `len`
+    /// bases of `other` from `j`
+    #[inline]
+    pub fn mismatches(&self, i: usize, other: &PackedSeq, j: usize, len: usize) -> usize {
+        assert!(i + len <= self.len && j + len <= other.len);
+        let mut n = 0;
+        let mut k = 0;
+        while k < len {
+            let mut x = self.window(i + k) ^ other.window(j + k);
+            let rest = len - k;
+            if rest < BASES {
+                x &= (1 << (2 * rest)) - 1;
+            }
+            n += ((x | (x >> 1)) & LOW_BITS).count_ones() as usize;
+            k += BASES;
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PackedSeq;
+    use bio_seq::prelude::*;
+
+    /// Reference scorer comparing one base at a time
+    fn scalar_mismatches(a: &SeqSlice<Dna>, b: &SeqSlice<Dna>) -> usize {
+        (0..a.len()).filter(|&i| a[i] != b[i]).count()
+    }
+
+    /// Deterministic pseudo-random reads
+    fn random_seq(len: usize, state: &mut u64) -> Seq<Dna> {
+        (0..len)
+            .map(|_| {
+                *state ^= *state << 13;
+                *state ^= *state >> 7;
+                *state ^= *state << 17;
+                [Dna::A, Dna::C, Dna::G, Dna::T][(*state >> 32) as usize % 4]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_scalar_agreement() {
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        for (n, m) in [(0, 0), (1, 1), (31, 33), (64, 64), (100, 71), (130, 129)] {
+            let r1 = random_seq(n, &mut state);
+            // r2 starts with a copy of r1's start so that some offsets have
+            // few mismatches
+            let mut r2 = random_seq(m, &mut state).to_string();
+            let shared = std::cmp::min(n, m) / 2;
+            r2.replace_range(..shared, &r1.to_string()[..shared]);
+            let r2: Seq<Dna> = r2.as_str().try_into().unwrap();
+
+            let (p1, p2) = (PackedSeq::new(&r1), PackedSeq::new(&r2));
+            assert_eq!(p1.len(), n);
+            for i in 0..=n {
+                for j in 0..=m {
+                    let len = std::cmp::min(n - i, m - j);
+                    assert_eq!(
+                        p1.mismatches(i, &p2, j, len),
+                        scalar_mismatches(&r1[i..i + len], &r2[j..j + len]),
+                        "{} {} {}",
+                        i,
+                        j,
+                        len
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_base() {
+        let a: Seq<Dna> = "ACGTACGTACGTACGTACGTACGTACGTACGTACGT".try_into().unwrap();
+        let b: Seq<Dna> = "CGTACGTACGTACGTACGTACGTACGTACGTACGTA".try_into().unwrap();
+        let (pa, pb) = (PackedSeq::new(&a), PackedSeq::new(&b));
+        assert_eq!(pa.mismatches(0, &pb, 0, 36), 36);
+        assert_eq!(pa.mismatches(1, &pb, 0, 35), 0);
+        assert_eq!(pa.mismatches(0, &pa, 4, 32), 0);
+        assert_eq!(pa.mismatches(33, &pb, 1, 3), 3);
+    }
+}
@@ -1,13 +1,19 @@
 use core::cmp::{max, min, Eq, Ordering};
 use std::fs::File;
 // use std::io;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::Path;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 
-use crate::mating::{mate, merge};
+use crate::mating::{
+    mate_and_merge, merge_read_through, read_through, short_insert, Mating, MatingParams,
+    Objective, UNKNOWN_QUAL,
+};
+use crate::trie::PrimerTrie;
 use bio_seq::prelude::*;
 
 #[derive(Debug, PartialEq)]
@@ -23,13 +29,31 @@ use Orientation::{F1R2, F2R1, R1F2, R2F1};
 #[derive(Debug, PartialEq)]
 pub enum Amplicon<'a> {
     Discarded,
-    Merged(Orientation, &'a Primer, &'a Primer, Seq<Dna>),
+    /// Overlapping mates merged into one sequence, with its phred qualities
+    Merged(Orientation, &'a Primer, &'a Primer, Seq<Dna>, Vec<u8>),
     Paired(Orientation, &'a Primer, &'a Primer),
+    /// Primers from different pools or reference sequences. Pairs of
+    /// non-adjacent targets in the same pool were once counted here too, they
+    /// are now `Spurious` along with every other pair that is not a target.
+    Chimeric(&'a Primer, &'a Primer),
+    /// Primers that do not define a known target or span an implausible length
+    Spurious(&'a Primer, &'a Primer),
+    /// Primers joined to each other with little or no insert between them
+    Dimer(&'a Primer, &'a Primer),
 }
 
-use Amplicon::{Discarded, Merged, Paired};
+use Amplicon::{Chimeric, Dimer, Discarded, Merged, Paired, Spurious};
 
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash)]
+/// Longest gap between two primers for which their product is considered a dimer
+const DIMER_GAP: usize = 10;
+
+/// Mismatches allowed between mates that read through a short insert
+const READ_THROUGH_MISMATCHES: usize = 3;
+
+/// Leading bytes of a primer set saved with `PrimerSet::to_binary`
+const BINARY_MAGIC: &[u8] = b"AMPS\x01";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct Primer {
     pub target: String,
     pub name: String,
@@ -38,76 +62,610 @@ pub struct Primer {
     //    left: bool,
     pub index: usize,
     //    length: u8,
+    #[serde(default)]
+    pub pool: Option<usize>,
+    /// Reference record the primer binds to, for multi-record references
+    #[serde(default)]
+    pub chrom: Option<String>,
+}
+
+impl Primer {
+    /// Name of the primer this one is an alternate of, or its own name
+    pub fn canonical(&self) -> &str {
+        canonical_name(&self.name)
+    }
+
+    pub fn is_alt(&self) -> bool {
+        self.canonical() != self.name
+    }
+}
+
+/// Version 1 primer spec: `name,forward,seq,index`
+///
+/// The target is derived from the primer name unless a `target` column is present.
+#[derive(Debug, Deserialize)]
+struct PrimerV1 {
+    #[serde(default)]
+    target: Option<String>,
+    name: String,
+    forward: bool,
+    seq: String,
+    index: usize,
+    #[serde(default)]
+    pool: Option<usize>,
+    #[serde(default)]
+    chrom: Option<String>,
+}
+
+impl From<PrimerV1> for Primer {
+    fn from(p: PrimerV1) -> Primer {
+        Primer {
+            target: p.target.unwrap_or_else(|| target_name(&p.name).to_string()),
+            name: p.name,
+            forward: p.forward,
+            seq: p.seq,
+            index: p.index,
+            pool: p.pool,
+            chrom: p.chrom,
+        }
+    }
+}
+
+/// Version 2 primer spec: `amplicon,name,left,forward,primer,position`
+///
+/// Primer names are relative to the amplicon, eg. `LEFT` or `RIGHT_alt`.
+#[derive(Debug, Deserialize)]
+struct PrimerV2 {
+    amplicon: String,
+    name: String,
+    #[allow(dead_code)]
+    left: bool,
+    forward: bool,
+    primer: String,
+    position: usize,
+}
+
+impl From<PrimerV2> for Primer {
+    fn from(p: PrimerV2) -> Primer {
+        Primer {
+            name: format!("{}_{}", p.amplicon, p.name),
+            target: p.amplicon,
+            forward: p.forward,
+            seq: p.primer,
+            index: p.position,
+            pool: None,
+            chrom: None,
+        }
+    }
+}
+
+/// Versions of the CSV primer spec, distinguished by their header row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrimerSpec {
+    V1,
+    V2,
+}
+
+impl PrimerSpec {
+    pub fn from_headers(headers: &csv::StringRecord) -> Option<PrimerSpec> {
+        let has = |col: &str| headers.iter().any(|h| h.trim() == col);
+
+        if ["amplicon", "name", "left", "forward", "primer", "position"]
+            .iter()
+            .all(|col| has(col))
+        {
+            Some(PrimerSpec::V2)
+        } else if ["name", "forward", "seq", "index"]
+            .iter()
+            .all(|col| has(col))
+        {
+            Some(PrimerSpec::V1)
+        } else {
+            None
+        }
+    }
+}
+
+/// Layout of a primer scheme file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrimerFormat {
+    /// Comma separated with a header row
+    Csv,
+    /// ARTIC/PrimalScheme `*.primer.bed`: chrom, start, end, name, pool, strand, seq
+    Bed,
+    /// A primer set saved with `PrimerSet::to_json`
+    Json,
+}
+
+impl PrimerFormat {
+    /// Guess the format from the first data line of a scheme file
+    pub fn detect(src: &str) -> PrimerFormat {
+        if src.trim_start().starts_with('{') {
+            return PrimerFormat::Json;
+        }
+        let line = src
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty() && !l.starts_with('#') && !is_bed_header(l));
+
+        match line {
+            Some(line) => {
+                let fields: Vec<&str> = line.split('\t').collect();
+                if fields.len() >= 4
+                    && fields[1].parse::<usize>().is_ok()
+                    && fields[2].parse::<usize>().is_ok()
+                {
+                    PrimerFormat::Bed
+                } else {
+                    PrimerFormat::Csv
+                }
+            }
+            None => PrimerFormat::Csv,
+        }
+    }
+}
+
+/// Whether a BED line is a UCSC `track` or `browser` line rather than a record
+fn is_bed_header(line: &str) -> bool {
+    ["track", "browser"].iter().any(|h| {
+        line.strip_prefix(h)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+    })
+}
+
+/// Strip the `_LEFT`/`_RIGHT` and alt suffixes from a primer name
+pub fn target_name(name: &str) -> &str {
+    match name.rfind("_LEFT").or_else(|| name.rfind("_RIGHT")) {
+        Some(i) => &name[..i],
+        None => match name.rfind("_alt") {
+            Some(i) => &name[..i],
+            None => name,
+        },
+    }
+}
+
+/// Strip alt suffixes so that alternate primers share their canonical primer's
+/// name, eg. `nCoV-2019_14_LEFT_alt4` and `SARS-CoV-2_1_LEFT_2` become
+/// `nCoV-2019_14_LEFT` and `SARS-CoV-2_1_LEFT`
+pub fn canonical_name(name: &str) -> &str {
+    for side in ["_LEFT", "_RIGHT"] {
+        if let Some(i) = name.rfind(side) {
+            return &name[..i + side.len()];
+        }
+    }
+    match name.rfind("_alt") {
+        Some(i) => &name[..i],
+        None => name,
+    }
+}
+
+/// Whether a read base is one of the bases that an IUPAC code stands for
+#[inline]
+pub fn compatible(p: Iupac, b: Dna) -> bool {
+    match b {
+        Dna::A => matches!(
+            p,
+            Iupac::A | Iupac::R | Iupac::W | Iupac::M | Iupac::D | Iupac::H | Iupac::V | Iupac::N
+        ),
+        Dna::C => matches!(
+            p,
+            Iupac::C | Iupac::Y | Iupac::S | Iupac::M | Iupac::B | Iupac::H | Iupac::V | Iupac::N
+        ),
+        Dna::G => matches!(
+            p,
+            Iupac::G | Iupac::R | Iupac::S | Iupac::K | Iupac::B | Iupac::D | Iupac::V | Iupac::N
+        ),
+        Dna::T => matches!(
+            p,
+            Iupac::T | Iupac::Y | Iupac::W | Iupac::K | Iupac::B | Iupac::D | Iupac::H | Iupac::N
+        ),
+    }
 }
 
-#[derive(Debug, Clone)]
+/// Hamming distance between a primer and the start of a read, giving up beyond `max`
+#[inline]
+pub fn hamming(primer: &SeqSlice<Iupac>, read: &SeqSlice<Dna>, max: usize) -> Option<usize> {
+    if primer.len() > read.len() {
+        return None;
+    }
+    let mut d = 0;
+    for (p, b) in primer.into_iter().zip(read.into_iter()) {
+        if !compatible(p, b) {
+            d += 1;
+            if d > max {
+                return None;
+            }
+        }
+    }
+    Some(d)
+}
+
+/// Pool numbers are written either as `1` or as `nCoV-2019_1`
+fn parse_pool(field: &str) -> Option<usize> {
+    let digits = field
+        .rsplit(|c: char| !c.is_ascii_digit())
+        .next()
+        .unwrap_or("");
+    digits.parse().ok()
+}
+
+/// Reference sequences in file order, named by the first word of their header
+#[derive(Debug, Clone, Default)]
+pub struct Reference {
+    pub records: Vec<(String, Seq<Dna>)>,
+}
+
+impl Reference {
+    pub fn new() -> Self {
+        Reference::default()
+    }
+
+    pub fn push(&mut self, name: &str, seq: Seq<Dna>) {
+        self.records.push((name.to_string(), seq));
+    }
+
+    /// Look up a record by name. Primers without a chromosome refer to the
+    /// first record, as for a single linear reference.
+    pub fn get(&self, chrom: Option<&str>) -> Option<&SeqSlice<Dna>> {
+        let (_, seq) = match chrom {
+            Some(chrom) => self.records.iter().find(|(name, _)| name == chrom)?,
+            None => self.records.first()?,
+        };
+        Some(seq)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.records.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+/// Reasons a primer scheme can fail to load
+#[derive(Debug)]
+pub enum PrimerSetError {
+    Io(std::io::Error),
+    /// A row that could not be parsed, with its line number in the scheme file
+    Malformed {
+        line: u64,
+        msg: String,
+    },
+    /// A primer containing something other than IUPAC nucleotide codes
+    InvalidBase {
+        line: u64,
+        name: String,
+        seq: String,
+    },
+    DuplicateName {
+        line: u64,
+        name: String,
+    },
+    /// A scheme name that is not in the built-in registry
+    UnknownScheme(String),
+    /// A target on a chromosome that is not in the reference
+    UnknownChrom {
+        name: String,
+        chrom: String,
+    },
+    /// A target whose coordinates fall outside of the reference sequence
+    OutOfBounds {
+        name: String,
+        start: usize,
+        end: usize,
+        len: usize,
+    },
+    /// A saved primer set that could not be encoded or decoded
+    Saved(String),
+}
+
+impl fmt::Display for PrimerSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrimerSetError::Io(e) => write!(f, "{}", e),
+            PrimerSetError::Malformed { line, msg } => write!(f, "line {}: {}", line, msg),
+            PrimerSetError::InvalidBase { line, name, seq } => {
+                write!(f, "line {}: invalid base in primer {}: {}", line, name, seq)
+            }
+            PrimerSetError::DuplicateName { line, name } => {
+                write!(f, "line {}: duplicate primer name {}", line, name)
+            }
+            PrimerSetError::UnknownScheme(name) => write!(f, "unknown primer scheme {}", name),
+            PrimerSetError::UnknownChrom { name, chrom } => {
+                write!(
+                    f,
+                    "target {} is on {}, which is not in the reference",
+                    name, chrom
+                )
+            }
+            PrimerSetError::OutOfBounds {
+                name,
+                start,
+                end,
+                len,
+            } => write!(
+                f,
+                "target {} ({}-{}) is outside of the reference (length {})",
+                name, start, end, len
+            ),
+            PrimerSetError::Saved(msg) => write!(f, "saved primer set: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for PrimerSetError {}
+
+impl From<std::io::Error> for PrimerSetError {
+    fn from(e: std::io::Error) -> Self {
+        PrimerSetError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for PrimerSetError {
+    fn from(e: serde_json::Error) -> Self {
+        match e.io_error_kind() {
+            Some(_) => PrimerSetError::Io(e.into()),
+            None => PrimerSetError::Saved(e.to_string()),
+        }
+    }
+}
+
+impl From<bincode::Error> for PrimerSetError {
+    fn from(e: bincode::Error) -> Self {
+        match *e {
+            bincode::ErrorKind::Io(e) => PrimerSetError::Io(e),
+            e => PrimerSetError::Saved(e.to_string()),
+        }
+    }
+}
+
+impl From<csv::Error> for PrimerSetError {
+    fn from(e: csv::Error) -> Self {
+        let line = e.position().map_or(0, |p| p.line());
+        let msg = e.to_string();
+        match e.into_kind() {
+            csv::ErrorKind::Io(e) => PrimerSetError::Io(e),
+            _ => PrimerSetError::Malformed { line, msg },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "SavedPrimerSet", try_from = "SavedPrimerSet")]
 pub struct PrimerSet {
     plen: usize,
-    pub forward: HashMap<Seq<Dna>, Primer>,
-    pub reverse: HashMap<Seq<Dna>, Primer>,
+    pub primers: Vec<(Seq<Iupac>, Primer)>,
+    trie: PrimerTrie,
+    /// Pairs of primers where one is a prefix of the other
+    pub collisions: Vec<(String, String)>,
     pub targets: HashMap<String, Target>,
+    /// Longest span of a primer pair accepted as an amplicon
+    max_amplicon: usize,
+    /// Hamming distance up to which inexact primer matches are accepted
+    pub max_mismatches: usize,
+    /// Furthest position from the start of a read at which a primer may begin
+    pub max_offset: usize,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// On-disk form of a `PrimerSet`. The trie and amplicon bound are rebuilt on load.
+#[derive(Serialize, Deserialize)]
+struct SavedPrimerSet {
+    plen: usize,
+    max_mismatches: usize,
+    max_offset: usize,
+    primers: Vec<Primer>,
+    targets: Vec<Target>,
+}
+
+impl From<PrimerSet> for SavedPrimerSet {
+    fn from(set: PrimerSet) -> SavedPrimerSet {
+        SavedPrimerSet {
+            plen: set.plen,
+            max_mismatches: set.max_mismatches,
+            max_offset: set.max_offset,
+            targets: ranked(&set.targets).into_iter().cloned().collect(),
+            primers: set.primers.into_iter().map(|(_, p)| p).collect(),
+        }
+    }
+}
+
+impl TryFrom<SavedPrimerSet> for PrimerSet {
+    type Error = PrimerSetError;
+
+    fn try_from(saved: SavedPrimerSet) -> Result<PrimerSet, PrimerSetError> {
+        let mut primers = Vec::new();
+        for (i, primer) in saved.primers.into_iter().enumerate() {
+            match primer.seq.as_str().try_into() {
+                Ok(seq) => primers.push((seq, primer)),
+                Err(_) => {
+                    return Err(PrimerSetError::InvalidBase {
+                        line: i as u64 + 1,
+                        name: primer.name,
+                        seq: primer.seq,
+                    })
+                }
+            }
+        }
+        let targets = saved
+            .targets
+            .into_iter()
+            .map(|t| (t.name.clone(), t))
+            .collect();
+
+        Ok(PrimerSet::assemble(saved.plen, primers, targets)
+            .with_max_mismatches(saved.max_mismatches)
+            .with_max_offset(saved.max_offset))
+    }
+}
+
+/// Outcome of identifying the primer at the start of a read
+#[derive(Debug, PartialEq)]
+pub enum PrimerMatch<'a> {
+    /// Primer and the offset at which it starts in the read
+    Exact(&'a Primer, usize),
+    /// Best primer, its Hamming distance from the read and its offset
+    Inexact(&'a Primer, usize, usize),
+    /// Several primers share the lowest Hamming distance
+    Ambiguous(Vec<&'a Primer>, usize),
+    Unmatched,
+}
+
+impl<'a> Amplicon<'a> {
+    /// The merged sequence without the primer-derived bases at either end
+    pub fn trimmed(&self) -> Option<&SeqSlice<Dna>> {
+        match self {
+            Merged(_, p1, p2, seq, _) => {
+                let (l, r) = (p1.seq.len(), p2.seq.len());
+                if seq.len() < l + r {
+                    return None;
+                }
+                Some(&seq[l..seq.len() - r])
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a> PrimerMatch<'a> {
+    pub fn primer(&self) -> Option<&'a Primer> {
+        match self {
+            PrimerMatch::Exact(p, _) | PrimerMatch::Inexact(p, _, _) => Some(*p),
+            _ => None,
+        }
+    }
+
+    /// Position in the read at which the primer starts
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            PrimerMatch::Exact(_, o) | PrimerMatch::Inexact(_, _, o) => Some(*o),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Target {
     pub name: String,
+    pub chrom: Option<String>,
+    /// Start of the outermost left primer
     pub start: usize,
+    /// End of the outermost right primer
     pub end: usize,
+    /// Start of the amplified region excluding every left primer
+    pub insert_start: usize,
+    /// End of the amplified region excluding every right primer
+    pub insert_end: usize,
+    #[serde(with = "opt_dna", default)]
     pub seq: Option<Seq<Dna>>,
 }
 
+/// Reference sequences are stored as plain strings in saved primer sets
+mod opt_dna {
+    use bio_seq::prelude::*;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(seq: &Option<Seq<Dna>>, s: S) -> Result<S::Ok, S::Error> {
+        seq.as_ref().map(|seq| seq.to_string()).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Seq<Dna>>, D::Error> {
+        match Option::<String>::deserialize(d)? {
+            Some(seq) => match seq.as_str().try_into() {
+                Ok(seq) => Ok(Some(seq)),
+                Err(_) => Err(D::Error::custom(format!(
+                    "invalid target sequence: {}",
+                    seq
+                ))),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// Targets in the order they appear along the reference
+fn ranked(targets: &HashMap<String, Target>) -> Vec<&Target> {
+    let mut ranked: Vec<&Target> = targets.values().collect();
+    ranked.sort_by_key(|t| (t.chrom.clone(), t.start, t.end));
+    ranked
+}
+
+/// Merge the mates of an on-target pair, along with the outcome of mating
+/// them if their overlap was searched for
 #[inline]
 fn merge_amplicon<'a>(
     p1: &'a Primer,
     r1: &SeqSlice<Dna>,
+    q1: &[u8],
     p2: &'a Primer,
     r2: &SeqSlice<Dna>,
-) -> Amplicon<'a> {
-    let max_indel = 84;
-    match (p1.index.cmp(&p2.index), p1.forward, p2.forward) {
+    q2: &[u8],
+    params: &MatingParams,
+) -> (Amplicon<'a>, Option<Mating>) {
+    // amplicons no longer than the reads are sequenced through into the adapter
+    if p1.index.abs_diff(p2.index) <= max(r1.len(), r2.len()) {
+        let min_insert = p1.seq.len() + p2.seq.len();
+        if let Some(insert) = read_through(r1, r2, min_insert, READ_THROUGH_MISMATCHES) {
+            let (orientation, first, second) =
+                match (p1.index.cmp(&p2.index), p1.forward, p2.forward) {
+                    (Ordering::Less, true, false) => (F1R2, p1, p2),
+                    (Ordering::Less, false, true) => (R1F2, p1, p2),
+                    (Ordering::Greater, true, false) => (F2R1, p2, p1),
+                    (Ordering::Greater, false, true) => (R2F1, p2, p1),
+                    _ => return (Discarded, None),
+                };
+            // the insert in the same orientation as the mates would be merged
+            let (seq, qual) = if p1.forward {
+                merge_read_through(r1, q1, r2, q2, insert)
+            } else {
+                merge_read_through(r2, q2, r1, q1, insert)
+            };
+            return (Merged(orientation, first, second, seq, qual), None);
+        }
+    }
+
+    // half the amplicon length, so that the expected overlap of the mates is
+    // their total length less the amplicon's
+    let span = p1.index.abs_diff(p2.index);
+    let hint = span / 2;
+    let mut mating = None;
+    let amplicon = match (p1.index.cmp(&p2.index), p1.forward, p2.forward) {
         (Ordering::Less, true, false) => {
             // F1R2
-            let hint = ((p2.index - p1.index) / 2) - 1;
-            let r2rc = r2.revcomp();
-            if hint < r1.len() - 30 {
-                match mate(r1, &r2rc, hint, max_indel) {
-                    Some(seam) => Merged(F1R2, p1, p2, merge(r1, &r2rc, seam)),
-                    None => Paired(F1R2, p1, p1),
-                }
-            } else {
-                Paired(F1R2, p1, p2)
+            let (r2rc, q2rc) = (r2.revcomp(), reversed(q2));
+            let (m, merged) = mate_and_merge(r1, q1, &r2rc, &q2rc, hint, params);
+            mating = m;
+            match merged {
+                Some((seq, qual)) => Merged(F1R2, p1, p2, seq, qual),
+                None => Paired(F1R2, p1, p2),
             }
         }
         (Ordering::Less, false, true) => {
             // R1F2
-            let hint = ((p2.index - p1.index) / 2) - 1;
-            let r1rc = r1.revcomp();
-            if hint < r1.len() - 30 {
-                match mate(&r1rc, r2, hint, max_indel) {
-                    Some(seam) => Merged(R1F2, p1, p2, merge(&r1rc, r2, seam)),
-                    None => Paired(R1F2, p1, p2),
-                }
-            } else {
-                Paired(R1F2, p1, p2)
+            let (r1rc, q1rc) = (r1.revcomp(), reversed(q1));
+            let (m, merged) = mate_and_merge(&r1rc, &q1rc, r2, q2, hint, params);
+            mating = m;
+            match merged {
+                Some((seq, qual)) => Merged(R1F2, p1, p2, seq, qual),
+                None => Paired(R1F2, p1, p2),
             }
         }
         (Ordering::Greater, true, false) => {
             // F2R1
-            let hint = ((p1.index - p2.index) / 2) - 1;
-            let r2rc = r2.revcomp();
-            match mate(&r2rc, r1, hint, max_indel) {
-                Some(seam) => Merged(F2R1, p2, p1, merge(&r2rc, r1, seam)),
-                None => Paired(F2R1, p2, p2),
+            let (r2rc, q2rc) = (r2.revcomp(), reversed(q2));
+            let (m, merged) = mate_and_merge(&r2rc, &q2rc, r1, q1, hint, params);
+            mating = m;
+            match merged {
+                Some((seq, qual)) => Merged(F2R1, p2, p1, seq, qual),
+                None => Paired(F2R1, p2, p1),
             }
         }
         (Ordering::Greater, false, true) => {
             // R2F1
-            let hint = ((p1.index - p2.index) / 2) - 1;
-            let r1rc = r1.revcomp();
-            match mate(r2, &r1rc, hint, max_indel) {
-                Some(seam) => {
+            let (r1rc, q1rc) = (r1.revcomp(), reversed(q1));
+            let (m, merged) = mate_and_merge(r2, q2, &r1rc, &q1rc, hint, params);
+            mating = m;
+            match merged {
+                Some((seq, qual)) => {
                     //                    println!("\tmerged: seam: {}, {}, {}, {}", seam, start, end, hint);
-                    Merged(R2F1, p2, p1, merge(r2, &r1rc, seam))
+                    Merged(R2F1, p2, p1, seq, qual)
                 }
                 None => {
                     //                    println!("\tpaired:\t{}\t{}\t{}\t{}", hint, end - start, start, end);
@@ -116,91 +674,558 @@ fn merge_amplicon<'a>(
             }
         }
         _ => Discarded,
+    };
+    // an odd amplicon length is rounded down by the hint, which expects one
+    // more base of overlap than there is
+    if let Some(m) = &mut mating {
+        m.offset += (span % 2) as isize;
     }
+    (amplicon, mating)
+}
+
+/// Qualities of a reverse complemented read
+fn reversed(qual: &[u8]) -> Vec<u8> {
+    qual.iter().rev().copied().collect()
+}
+
+/// Whether a pair of reads is a primer dimer: the insert between the primers is
+/// at most `DIMER_GAP` long, and the primers may overlap at their 3' ends
+fn is_dimer(p1: &Primer, r1: &SeqSlice<Dna>, p2: &Primer, r2: &SeqSlice<Dna>) -> bool {
+    let (l1, l2) = (p1.seq.len(), p2.seq.len());
+    short_insert(r1, r2, max(l1, l2)..=l1 + l2 + DIMER_GAP, 1).is_some()
+}
+
+/// Whether a read starting with primer `p` and its unmatched mate read through
+/// a product of little more than the primer itself into the adapter. The mate
+/// then starts with the reverse complement of the primer, so no primer is
+/// found in it.
+fn is_primer_read_through(p: &Primer, r: &SeqSlice<Dna>, mate: &SeqSlice<Dna>) -> bool {
+    let l = p.seq.len();
+    short_insert(r, mate, l..=l + DIMER_GAP, 1).is_some()
 }
 
 impl PrimerSet {
-    pub fn from_csv(src: &PathBuf, ref_seq: Option<&SeqSlice<Dna>>) -> PrimerSet {
-        PrimerSet::from_reader(File::open(src).unwrap(), ref_seq)
+    /// Load a primer scheme, detecting whether it is CSV, BED or a saved primer
+    /// set from its content
+    ///
+    /// Saved primer sets are only annotated with the reference if they were
+    /// saved without target sequences.
+    pub fn from_path(
+        src: &Path,
+        reference: Option<&Reference>,
+    ) -> Result<PrimerSet, PrimerSetError> {
+        let mut buf = Vec::new();
+        File::open(src)?.read_to_end(&mut buf)?;
+
+        let mut set = if buf.starts_with(BINARY_MAGIC) {
+            PrimerSet::from_binary(buf.as_slice())?
+        } else {
+            let buf = String::from_utf8(buf).map_err(|_| PrimerSetError::Malformed {
+                line: 0,
+                msg: "not a text or saved primer set file".to_string(),
+            })?;
+            match PrimerFormat::detect(&buf) {
+                PrimerFormat::Bed => return PrimerSet::from_bed(buf.as_bytes(), reference),
+                PrimerFormat::Csv => return PrimerSet::from_reader(buf.as_bytes(), reference),
+                PrimerFormat::Json => PrimerSet::from_json(buf.as_bytes())?,
+            }
+        };
+
+        if let Some(reference) = reference {
+            if !set.is_annotated() {
+                set.annotate(reference)?;
+            }
+        }
+        Ok(set)
+    }
+
+    /// Whether `src` holds a saved primer set, which carries its own matching
+    /// thresholds, rather than a CSV or BED scheme
+    pub fn is_saved(src: &Path) -> Result<bool, PrimerSetError> {
+        let mut head = Vec::new();
+        File::open(src)?.take(1024).read_to_end(&mut head)?;
+        Ok(head.starts_with(BINARY_MAGIC)
+            || String::from_utf8_lossy(&head).trim_start().starts_with('{'))
+    }
+
+    /// Write the primer set, including any reference-derived target sequences, as JSON
+    pub fn to_json<W: Write>(&self, dst: W) -> Result<(), PrimerSetError> {
+        Ok(serde_json::to_writer_pretty(dst, self)?)
+    }
+
+    pub fn from_json<R: Read>(src: R) -> Result<PrimerSet, PrimerSetError> {
+        Ok(serde_json::from_reader(src)?)
+    }
+
+    /// Write the primer set in a compact binary form for fast reloading
+    pub fn to_binary<W: Write>(&self, mut dst: W) -> Result<(), PrimerSetError> {
+        dst.write_all(BINARY_MAGIC)?;
+        Ok(bincode::serialize_into(dst, self)?)
+    }
+
+    pub fn from_binary<R: Read>(mut src: R) -> Result<PrimerSet, PrimerSetError> {
+        let mut magic = [0; BINARY_MAGIC.len()];
+        src.read_exact(&mut magic)?;
+        if magic != BINARY_MAGIC {
+            return Err(PrimerSetError::Saved("not a binary primer set".to_string()));
+        }
+        Ok(bincode::deserialize_from(src)?)
+    }
+
+    pub fn from_csv(
+        src: &Path,
+        reference: Option<&Reference>,
+    ) -> Result<PrimerSet, PrimerSetError> {
+        PrimerSet::from_reader(File::open(src)?, reference)
+    }
+
+    pub fn from_reader<R: std::io::Read>(
+        src: R,
+        reference: Option<&Reference>,
+    ) -> Result<PrimerSet, PrimerSetError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(src);
+
+        let headers = rdr.headers()?.clone();
+        let spec = PrimerSpec::from_headers(&headers).ok_or_else(|| PrimerSetError::Malformed {
+            line: 1,
+            msg: format!("unrecognised primer spec header: {}", headers.as_slice()),
+        })?;
+
+        let mut primers = Vec::new();
+
+        for result in rdr.records() {
+            let record = result?;
+            let line = record.position().map_or(0, |p| p.line());
+            let primer = match spec {
+                PrimerSpec::V1 => record.deserialize::<PrimerV1>(Some(&headers))?.into(),
+                PrimerSpec::V2 => record.deserialize::<PrimerV2>(Some(&headers))?.into(),
+            };
+            primers.push((line, primer));
+        }
+
+        PrimerSet::from_primers(primers, reference)
     }
 
-    pub fn from_reader<R: std::io::Read>(src: R, ref_seq: Option<&SeqSlice<Dna>>) -> PrimerSet {
-        let mut rdr = csv::Reader::from_reader(src);
+    /// Read an ARTIC/PrimalScheme `*.primer.bed` file.
+    ///
+    /// The target name is the primer name without its `_LEFT`/`_RIGHT` and alt
+    /// suffixes, the strand column decides orientation and the pool column may
+    /// be either `1` or `nCoV-2019_1`.
+    pub fn from_bed<R: std::io::Read>(
+        src: R,
+        reference: Option<&Reference>,
+    ) -> Result<PrimerSet, PrimerSetError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .has_headers(false)
+            .flexible(true)
+            .comment(Some(b'#'))
+            .from_reader(src);
+
+        let mut primers = Vec::new();
+
+        for result in rdr.records() {
+            let record = result?;
+            if is_bed_header(&record[0]) {
+                continue;
+            }
+            let line = record.position().map_or(0, |p| p.line());
+            let malformed = |msg: &str| PrimerSetError::Malformed {
+                line,
+                msg: msg.to_string(),
+            };
+
+            if record.len() < 7 {
+                return Err(malformed(
+                    "expected 7 columns: chrom, start, end, name, pool, strand, seq",
+                ));
+            }
+
+            let start: usize = record[1]
+                .parse()
+                .map_err(|_| malformed("start is not a number"))?;
+            let name = record[3].to_string();
+            let forward = match &record[5] {
+                "+" => true,
+                "-" => false,
+                _ => !name.contains("_RIGHT"),
+            };
+
+            primers.push((
+                line,
+                Primer {
+                    target: target_name(&name).to_string(),
+                    forward,
+                    seq: record[6].to_string(),
+                    index: start,
+                    pool: parse_pool(&record[4]),
+                    chrom: Some(record[0].to_string()),
+                    name,
+                },
+            ));
+        }
 
-        let mut forward = HashMap::new();
-        let mut reverse = HashMap::new();
+        PrimerSet::from_primers(primers, reference)
+    }
+
+    fn from_primers(
+        records: Vec<(u64, Primer)>,
+        reference: Option<&Reference>,
+    ) -> Result<PrimerSet, PrimerSetError> {
         let mut targets: HashMap<String, Target> = HashMap::new();
+        let mut names: HashSet<String> = HashSet::new();
         let mut pmax = 0;
         let mut plen = 100;
 
         let mut primers = Vec::new();
 
-        for result in rdr.deserialize() {
-            let rx: Primer = result.unwrap();
+        for (line, rx) in records {
+            if !names.insert(rx.name.clone()) {
+                return Err(PrimerSetError::DuplicateName {
+                    line,
+                    name: rx.name,
+                });
+            }
+            let seq: Seq<Iupac> = match rx.seq.as_str().try_into() {
+                Ok(seq) => seq,
+                Err(_) => {
+                    return Err(PrimerSetError::InvalidBase {
+                        line,
+                        name: rx.name,
+                        seq: rx.seq,
+                    })
+                }
+            };
             let index = if rx.forward {
                 rx.index
             } else {
                 rx.index + rx.seq.len()
             };
-            let record = Primer {
-                target: rx.target.clone(),
-                name: rx.name,
-                forward: rx.forward,
-                seq: rx.seq,
-                index,
-            };
+            let target_name = rx.target.clone();
+            let chrom = rx.chrom.clone();
+            let record = Primer { index, ..rx };
             let l = record.seq.len();
+            let forward = record.forward;
             plen = min(l, plen);
             pmax = max(l, pmax);
-            primers.push(record);
+            primers.push((seq, record));
 
-            let target = targets.entry(rx.target.clone()).or_insert_with(|| Target {
-                name: rx.target.clone(),
-                start: index,
-                end: index,
-                seq: None,
-            });
+            let target = targets
+                .entry(target_name.clone())
+                .or_insert_with(|| Target {
+                    name: target_name,
+                    chrom,
+                    start: index,
+                    end: index,
+                    insert_start: 0,
+                    insert_end: usize::MAX,
+                    seq: None,
+                });
 
             target.start = min(target.start, index);
             target.end = max(target.end, index);
-        }
-
-        for primer in primers {
-            if primer.forward {
-                forward.insert(primer.seq[..plen].try_into().unwrap(), primer.clone());
+            if forward {
+                target.insert_start = max(target.insert_start, index + l);
             } else {
-                reverse.insert(primer.seq[..plen].try_into().unwrap(), primer.clone());
+                target.insert_end = min(target.insert_end, index.saturating_sub(l));
             }
         }
 
-        if let Some(seq) = ref_seq {
-            for target in targets.values_mut() {
-                target.seq = Some(seq[target.start..target.end].into());
-                println!("{:?}", target);
+        // targets missing a primer on one side are bounded by their outermost primer
+        for target in targets.values_mut() {
+            target.insert_start = max(target.insert_start, target.start);
+            target.insert_end = min(target.insert_end, target.end);
+        }
+
+        let mut set = PrimerSet::assemble(plen, primers, targets);
+
+        if let Some(reference) = reference {
+            set.annotate(reference)?;
+        }
+        Ok(set)
+    }
+
+    /// Build the primer trie and target ranks for a set of parsed primers
+    fn assemble(
+        plen: usize,
+        primers: Vec<(Seq<Iupac>, Primer)>,
+        targets: HashMap<String, Target>,
+    ) -> PrimerSet {
+        let mut trie = PrimerTrie::new();
+        let mut collisions = Vec::new();
+
+        for (id, (seq, primer)) in primers.iter().enumerate() {
+            for other in trie.insert(seq, id) {
+                collisions.push((primers[other].1.name.clone(), primer.name.clone()));
             }
         }
 
+        // twice the median target length, so that a target whose primers sit
+        // kilobases apart is not taken for an amplicon
+        let mut lens: Vec<usize> = targets.values().map(|t| t.end - t.start).collect();
+        lens.sort_unstable();
+        let max_amplicon = lens.get(lens.len() / 2).map_or(0, |len| 2 * len);
+
         PrimerSet {
             plen,
-            forward,
-            reverse,
+            max_amplicon,
+            primers,
+            trie,
+            collisions,
             targets,
+            max_mismatches: 0,
+            max_offset: 0,
         }
     }
-    pub fn get(&self, p: &SeqSlice<Dna>) -> Option<&Primer> {
-        match self.forward.get(&p[..self.plen]) {
-            Some(p) => Some(p),
-            None => match self.reverse.get(&p[..self.plen]) {
-                Some(p) => Some(p),
-                None => None,
-            },
+
+    /// Whether every target already carries its reference sequence
+    pub fn is_annotated(&self) -> bool {
+        self.targets.values().all(|t| t.seq.is_some())
+    }
+
+    /// Attach the reference sequence of each target
+    pub fn annotate(&mut self, reference: &Reference) -> Result<(), PrimerSetError> {
+        for target in self.targets.values_mut() {
+            let seq = match reference.get(target.chrom.as_deref()) {
+                Some(seq) => seq,
+                None => {
+                    return Err(PrimerSetError::UnknownChrom {
+                        name: target.name.clone(),
+                        chrom: target.chrom.clone().unwrap_or_default(),
+                    })
+                }
+            };
+            if target.end > seq.len() {
+                return Err(PrimerSetError::OutOfBounds {
+                    name: target.name.clone(),
+                    start: target.start,
+                    end: target.end,
+                    len: seq.len(),
+                });
+            }
+            target.seq = Some(seq[target.start..target.end].into());
+        }
+        Ok(())
+    }
+    /// Accept primer matches with up to `n` mismatches
+    pub fn with_max_mismatches(mut self, n: usize) -> Self {
+        self.max_mismatches = n;
+        self
+    }
+
+    /// Allow primers to start up to `n` bases into a read, eg. after an inline
+    /// barcode or spacer
+    pub fn with_max_offset(mut self, n: usize) -> Self {
+        self.max_offset = n;
+        self
+    }
+
+    /// All primers in the scheme
+    pub fn iter(&self) -> impl Iterator<Item = &Primer> {
+        self.primers.iter().map(|(_, primer)| primer)
+    }
+
+    /// A canonical primer and its alternates
+    pub fn alternates<'b>(&'b self, canonical: &'b str) -> impl Iterator<Item = &'b Primer> {
+        self.iter().filter(move |p| p.canonical() == canonical)
+    }
+
+    /// The target of a merged amplicon and its insert, trimmed to the target's
+    /// insert coordinates so that products of alternate primers are identical
+    /// to those of the canonical primers
+    pub fn amplicon_insert<'b>(
+        &self,
+        amplicon: &'b Amplicon,
+    ) -> Option<(&Target, &'b SeqSlice<Dna>)> {
+        let (p1, p2, seq) = match amplicon {
+            Merged(_, p1, p2, seq, _) if p1.forward && !p2.forward => (p1, p2, seq),
+            _ => return None,
+        };
+        let target = self.targets.get(&p1.target)?;
+        let start = target.insert_start.checked_sub(p1.index)?;
+        let end = p2.index.checked_sub(target.insert_end)?;
+        if seq.len() < start + end {
+            return None;
+        }
+        Some((target, &seq[start..seq.len() - end]))
+    }
+
+    /// Identify the primer at the start of a read.
+    ///
+    /// Primers are matched over their whole length and the longest match wins.
+    pub fn get_exact(&self, p: &SeqSlice<Dna>) -> Option<&Primer> {
+        if p.len() < self.plen {
+            return None;
         }
+        self.trie.longest(p).map(|id| &self.primers[id].1)
     }
-    pub fn get_amplicon(&self, r1: &SeqSlice<Dna>, r2: &SeqSlice<Dna>) -> Amplicon {
-        match (self.get(r1), self.get(r2)) {
-            (Some(p1), Some(p2)) => merge_amplicon(p1, r1, p2, r2),
-            //                            *bins.entry((p1.name.clone(), p2.name.clone())).or_insert(1) += 1;
-            _ => Amplicon::Discarded,
+
+    /// Identify the primer within the first `max_offset` bases of a read, falling
+    /// back to the primer with the lowest Hamming distance up to `max_mismatches`
+    pub fn find(&self, p: &SeqSlice<Dna>) -> PrimerMatch<'_> {
+        let offsets = 0..=min(self.max_offset, p.len().saturating_sub(self.plen));
+
+        for offset in offsets.clone() {
+            if let Some(primer) = self.get_exact(&p[offset..]) {
+                return PrimerMatch::Exact(primer, offset);
+            }
+        }
+        if self.max_mismatches == 0 {
+            return PrimerMatch::Unmatched;
+        }
+
+        let mut best = self.max_mismatches + 1;
+        let mut hits: Vec<(&Primer, usize)> = Vec::new();
+
+        for offset in offsets {
+            for (seq, primer) in &self.primers {
+                if let Some(d) = hamming(seq, &p[offset..], min(best, self.max_mismatches)) {
+                    match d.cmp(&best) {
+                        Ordering::Less => {
+                            best = d;
+                            hits.clear();
+                            hits.push((primer, offset));
+                        }
+                        Ordering::Equal => {
+                            if !hits.iter().any(|(hit, _)| hit.name == primer.name) {
+                                hits.push((primer, offset));
+                            }
+                        }
+                        Ordering::Greater => (),
+                    }
+                }
+            }
+        }
+
+        match hits.len() {
+            0 => PrimerMatch::Unmatched,
+            1 => PrimerMatch::Inexact(hits[0].0, best, hits[0].1),
+            _ => PrimerMatch::Ambiguous(hits.into_iter().map(|(p, _)| p).collect(), best),
+        }
+    }
+
+    /// Whether two primers could come from the same reaction: the same
+    /// reference sequence and the same pool (when known). Target adjacency is
+    /// left to `on_target`, so same-pool pairs of distant targets are spurious
+    /// rather than chimeric.
+    pub fn compatible_pair(&self, p1: &Primer, p2: &Primer) -> bool {
+        if p1.chrom != p2.chrom {
+            return false;
+        }
+        match (p1.pool, p2.pool) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+    }
+
+    /// Whether a primer pair defines one of the scheme's targets: opposite
+    /// strands of the same target, facing each other and spanning no more than
+    /// twice the scheme's median amplicon
+    pub fn on_target(&self, p1: &Primer, p2: &Primer) -> bool {
+        if p1.target != p2.target || p1.forward == p2.forward {
+            return false;
+        }
+        if !self.targets.contains_key(&p1.target) {
+            return false;
+        }
+        let (left, right) = if p1.forward { (p1, p2) } else { (p2, p1) };
+        left.index < right.index && right.index - left.index <= self.max_amplicon
+    }
+
+    /// The primer found in a read and the offset at which it starts
+    pub fn get(&self, p: &SeqSlice<Dna>) -> Option<(&Primer, usize)> {
+        let m = self.find(p);
+        Some((m.primer()?, m.offset()?))
+    }
+
+    /// Classify a read pair without base qualities, see `get_amplicon_qual`
+    pub fn get_amplicon(
+        &self,
+        r1: &SeqSlice<Dna>,
+        r2: &SeqSlice<Dna>,
+        params: &MatingParams,
+        stats: &mut Stats,
+    ) -> Amplicon<'_> {
+        let q1 = vec![UNKNOWN_QUAL; r1.len()];
+        let q2 = vec![UNKNOWN_QUAL; r2.len()];
+        self.get_amplicon_qual(r1, &q1, r2, &q2, params, stats)
+    }
+
+    /// Classify a read pair by its primers and merge overlapping mates, using
+    /// the phred qualities of each read to reconcile the overlap
+    pub fn get_amplicon_qual(
+        &self,
+        r1: &SeqSlice<Dna>,
+        q1: &[u8],
+        r2: &SeqSlice<Dna>,
+        q2: &[u8],
+        params: &MatingParams,
+        stats: &mut Stats,
+    ) -> Amplicon<'_> {
+        // qualities that do not cover their reads are not used
+        if q1.len() != r1.len() || q2.len() != r2.len() {
+            return self.get_amplicon(r1, r2, params, stats);
+        }
+
+        let m1 = self.find(r1);
+        let m2 = self.find(r2);
+        stats.total_pairs += 1;
+        stats.record_match(&m1, r1);
+        stats.record_match(&m2, r2);
+
+        match (m1.primer(), m2.primer()) {
+            (Some(p1), Some(p2)) => {
+                stats.matched += 1;
+                stats.record_pools(p1, p2);
+                // trim anything preceding the primers
+                let (o1, o2) = (m1.offset().unwrap_or(0), m2.offset().unwrap_or(0));
+                let (r1, q1) = (&r1[o1..], &q1[o1..]);
+                let (r2, q2) = (&r2[o2..], &q2[o2..]);
+                if is_dimer(p1, r1, p2, r2) {
+                    stats.record_dimer(p1, p2);
+                    return Dimer(p1, p2);
+                }
+                if !self.compatible_pair(p1, p2) {
+                    stats.record_chimera(p1, p2);
+                    return Chimeric(p1, p2);
+                }
+                if !self.on_target(p1, p2) {
+                    stats.record_spurious(p1, p2);
+                    return Spurious(p1, p2);
+                }
+                stats.on_target += 1;
+                stats.record_usage(p1);
+                stats.record_usage(p2);
+                let (amplicon, mating) = merge_amplicon(p1, r1, q1, p2, r2, q2, params);
+                match &mating {
+                    Some(mating) => stats.record_mating(p1, mating, params.objective),
+                    None if !matches!(amplicon, Merged(..)) => stats.record_unscored(p1),
+                    None => (),
+                }
+                if let Merged(_, _, _, seq, _) = &amplicon {
+                    stats.record_insert(p1, seq.len());
+                }
+                amplicon
+            }
+            (Some(p), None) | (None, Some(p)) => {
+                let (m, r, mate) = if m1.primer().is_some() {
+                    (&m1, r1, r2)
+                } else {
+                    (&m2, r2, r1)
+                };
+                if is_primer_read_through(p, &r[m.offset().unwrap_or(0)..], mate) {
+                    stats.record_dimer(p, p);
+                    return Dimer(p, p);
+                }
+                stats.off_target += 1;
+                Amplicon::Discarded
+            }
+            _ => {
+                stats.off_target += 1;
+                Amplicon::Discarded
+            }
         }
     }
 }
@@ -212,4 +1237,870 @@ pub struct Stats {
     pub total_pairs: u32,
     pub matched: u32,
     pub mated: u32,
+    pub ambiguous: u32,
+    /// Inexact primer sites observed in reads, keyed by primer name
+    pub inexact: HashMap<(String, Seq<Dna>), u32>,
+    pub chimeric: u32,
+    /// Primer pairs from different reactions, keyed by primer names
+    pub chimeras: HashMap<(String, String), u32>,
+    /// Matched pairs counted by the pools of their primers
+    pub pool_pairs: HashMap<(Option<usize>, Option<usize>), u32>,
+    /// Primer pairs that do not define a target, keyed by primer names
+    pub spurious: HashMap<(String, String), u32>,
+    /// Primer dimers, keyed by primer names
+    pub dimers: HashMap<(String, String), u32>,
+    /// On-target pairs each primer took part in, keyed by primer name
+    pub usage: HashMap<String, u32>,
+    /// Lengths of merged amplicons, keyed by target name
+    pub insert_sizes: HashMap<String, BTreeMap<usize, u32>>,
+    /// Best overlaps of mated pairs binned by `Objective::bin`, keyed by target
+    /// name
+    pub overlap_scores: HashMap<String, BTreeMap<i64, u32>>,
+    /// On-target pairs left unmerged without an overlap to score, keyed by
+    /// target name
+    pub unscored: HashMap<String, u32>,
+}
+
+/// Primer names of a pair in a consistent order
+fn pair_key(p1: &Primer, p2: &Primer) -> (String, String) {
+    if p1.name <= p2.name {
+        (p1.name.clone(), p2.name.clone())
+    } else {
+        (p2.name.clone(), p1.name.clone())
+    }
+}
+
+fn top_pairs(pairs: &HashMap<(String, String), u32>, n: usize) -> Vec<(&(String, String), u32)> {
+    let mut pairs: Vec<(&(String, String), u32)> = pairs.iter().map(|(k, v)| (k, *v)).collect();
+    pairs.sort_by(|a, b| b.1.cmp(&a.1));
+    pairs.truncate(n);
+    pairs
+}
+
+impl Stats {
+    pub fn record_match(&mut self, m: &PrimerMatch, read: &SeqSlice<Dna>) {
+        match m {
+            PrimerMatch::Inexact(primer, _, offset) => {
+                let site: Seq<Dna> = read[*offset..*offset + primer.seq.len()].into();
+                *self.inexact.entry((primer.name.clone(), site)).or_insert(0) += 1;
+            }
+            PrimerMatch::Ambiguous(_, _) => self.ambiguous += 1,
+            _ => (),
+        }
+    }
+
+    pub fn record_pools(&mut self, p1: &Primer, p2: &Primer) {
+        let key = (min(p1.pool, p2.pool), max(p1.pool, p2.pool));
+        *self.pool_pairs.entry(key).or_insert(0) += 1;
+    }
+
+    pub fn record_chimera(&mut self, p1: &Primer, p2: &Primer) {
+        self.chimeric += 1;
+        *self.chimeras.entry(pair_key(p1, p2)).or_insert(0) += 1;
+    }
+
+    pub fn record_spurious(&mut self, p1: &Primer, p2: &Primer) {
+        *self.spurious.entry(pair_key(p1, p2)).or_insert(0) += 1;
+    }
+
+    pub fn record_dimer(&mut self, p1: &Primer, p2: &Primer) {
+        *self.dimers.entry(pair_key(p1, p2)).or_insert(0) += 1;
+    }
+
+    pub fn record_usage(&mut self, p: &Primer) {
+        *self.usage.entry(p.name.clone()).or_insert(0) += 1;
+    }
+
+    /// Count a merged amplicon of `len` bases towards the target of `p`
+    pub fn record_insert(&mut self, p: &Primer, len: usize) {
+        *self
+            .insert_sizes
+            .entry(p.target.clone())
+            .or_default()
+            .entry(len)
+            .or_insert(0) += 1;
+    }
+
+    /// Count the best overlap of a pair towards the target of `p`
+    pub fn record_mating(&mut self, p: &Primer, mating: &Mating, objective: Objective) {
+        if mating.accepted {
+            self.mated += 1;
+        }
+        *self
+            .overlap_scores
+            .entry(p.target.clone())
+            .or_default()
+            .entry(objective.bin(mating))
+            .or_insert(0) += 1;
+    }
+
+    /// Count a pair of the target of `p` that was not merged and had no
+    /// overlap to score
+    pub fn record_unscored(&mut self, p: &Primer) {
+        *self.unscored.entry(p.target.clone()).or_insert(0) += 1;
+    }
+
+    /// Usage of each primer that has an alternate, as `(canonical, name, count)`
+    /// sorted by canonical name
+    pub fn alt_usage(&self) -> Vec<(&str, &str, u32)> {
+        let groups: HashSet<&str> = self
+            .usage
+            .keys()
+            .filter(|name| canonical_name(name) != name.as_str())
+            .map(|name| canonical_name(name))
+            .collect();
+        let mut usage: Vec<(&str, &str, u32)> = self
+            .usage
+            .iter()
+            .map(|(name, count)| (canonical_name(name), name.as_str(), *count))
+            .filter(|(canonical, _, _)| groups.contains(canonical))
+            .collect();
+        usage.sort();
+        usage
+    }
+
+    /// The `n` most frequent primer dimers
+    pub fn top_dimers(&self, n: usize) -> Vec<(&(String, String), u32)> {
+        top_pairs(&self.dimers, n)
+    }
+
+    /// The `n` most frequent chimeric primer pairs
+    pub fn top_chimeras(&self, n: usize) -> Vec<(&(String, String), u32)> {
+        top_pairs(&self.chimeras, n)
+    }
+
+    /// The `n` most frequent spurious primer pairs
+    pub fn top_spurious(&self, n: usize) -> Vec<(&(String, String), u32)> {
+        top_pairs(&self.spurious, n)
+    }
+
+    /// The `n` most frequently observed inexact primer sites
+    pub fn top_inexact(&self, n: usize) -> Vec<(&String, &Seq<Dna>, u32)> {
+        let mut sites: Vec<(&String, &Seq<Dna>, u32)> = self
+            .inexact
+            .iter()
+            .map(|((name, site), count)| (name, site, *count))
+            .collect();
+        sites.sort_by(|a, b| b.2.cmp(&a.2));
+        sites.truncate(n);
+        sites
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        canonical_name, target_name, Amplicon, Orientation, PrimerFormat, PrimerMatch, PrimerSet,
+        PrimerSetError, Reference, Stats,
+    };
+    use crate::mating::MatingParams;
+    use bio_seq::prelude::*;
+    use std::collections::BTreeMap;
+
+    const ARTIC_V3: &str = include_str!("../schemes/artic-v3.csv");
+    const ARTIC_V41: &str = include_str!("../schemes/artic-v4.1.csv");
+
+    const BED: &str =
+        "MN908947.3\t30\t54\tnCoV-2019_1_LEFT\tnCoV-2019_1\t+\tACCAACCAACTTTCGATCTCTTGT
+MN908947.3\t385\t410\tnCoV-2019_1_RIGHT\tnCoV-2019_1\t-\tCATCTTTAAGATGTTGACGTGCCTC
+MN908947.3\t320\t342\tnCoV-2019_2_LEFT\tnCoV-2019_2\t+\tCTGTTTTACAGGTTCGCGACGT
+MN908947.3\t704\t726\tnCoV-2019_2_RIGHT\tnCoV-2019_2\t-\tTAAGGATCAGTGCCAAGCTCGT
+MN908947.3\t1868\t1890\tnCoV-2019_7_LEFT_alt0\t1\t+\tCATTTGCATCAGAGGCTGCTCG
+";
+
+    #[test]
+    fn test_target_name() {
+        assert_eq!(target_name("nCoV-2019_1_LEFT"), "nCoV-2019_1");
+        assert_eq!(target_name("nCoV-2019_14_LEFT_alt4"), "nCoV-2019_14");
+        assert_eq!(target_name("SARS-CoV-2_1_RIGHT_1"), "SARS-CoV-2_1");
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(PrimerFormat::detect(BED), PrimerFormat::Bed);
+        assert_eq!(
+            PrimerFormat::detect("name,forward,seq,index\nnCoV-2019_1_LEFT,true,ACGT,30\n"),
+            PrimerFormat::Csv
+        );
+    }
+
+    #[test]
+    fn test_from_bed() {
+        let primers = PrimerSet::from_bed(BED.as_bytes(), None).unwrap();
+        let target = primers.targets.get("nCoV-2019_1").unwrap();
+        assert_eq!(target.start, 30);
+        assert_eq!(target.end, 410);
+
+        let alt = primers
+            .iter()
+            .find(|p| p.name == "nCoV-2019_7_LEFT_alt0")
+            .unwrap();
+        assert_eq!(alt.target, "nCoV-2019_7");
+        assert_eq!(alt.pool, Some(1));
+        assert!(primers
+            .iter()
+            .all(|p| p.pool == Some(1) || p.pool == Some(2)));
+
+        let headed = format!(
+            "browser position MN908947.3:1-1000\ntrack name=primers description=\"ARTIC v3\"\n{}",
+            BED
+        );
+        assert_eq!(PrimerFormat::detect(&headed), PrimerFormat::Bed);
+        let primers = PrimerSet::from_bed(headed.as_bytes(), None).unwrap();
+        assert_eq!(primers.targets.get("nCoV-2019_1").unwrap().start, 30);
+    }
+
+    #[test]
+    fn test_bundled_schemes() {
+        let v3 = PrimerSet::from_reader(ARTIC_V3.as_bytes(), None).unwrap();
+        assert_eq!(v3.targets.len(), 98);
+        let target = v3.targets.get("nCoV-2019_1").unwrap();
+        assert_eq!((target.start, target.end), (30, 410));
+
+        let v41 = PrimerSet::from_reader(ARTIC_V41.as_bytes(), None).unwrap();
+        assert_eq!(v41.targets.len(), 99);
+    }
+
+    #[test]
+    fn test_spec_v2() {
+        let v2 = "amplicon,name,left,forward,primer,position
+nCoV-2019_1,LEFT,true,true,ACCAACCAACTTTCGATCTCTTGT,30
+nCoV-2019_1,RIGHT,false,false,CATCTTTAAGATGTTGACGTGCCTC,385
+nCoV-2019_1,RIGHT_alt,false,false,GTCTTTAAGATGTTGACGTGCC,388
+";
+        let primers = PrimerSet::from_reader(v2.as_bytes(), None).unwrap();
+        assert_eq!(primers.targets.len(), 1);
+        assert!(primers
+            .iter()
+            .any(|p| p.name == "nCoV-2019_1_RIGHT_alt" && p.target == "nCoV-2019_1"));
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let bad_base = "name,forward,seq,index
+nCoV-2019_1_LEFT,true,ACCAACCAACTTTCGATCTCTTGT,30
+nCoV-2019_1_RIGHT,false,CATCTTTAAGATGTTGAXGTGCCTC,385
+";
+        match PrimerSet::from_reader(bad_base.as_bytes(), None) {
+            Err(PrimerSetError::InvalidBase { line, name, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(name, "nCoV-2019_1_RIGHT");
+            }
+            r => panic!("expected InvalidBase, got {:?}", r.map(|_| ())),
+        }
+
+        let duplicate = "name,forward,seq,index
+nCoV-2019_1_LEFT,true,ACCAACCAACTTTCGATCTCTTGT,30
+nCoV-2019_1_LEFT,true,ACCAACCAACTTTCGATCTCTTGT,30
+";
+        assert!(matches!(
+            PrimerSet::from_reader(duplicate.as_bytes(), None),
+            Err(PrimerSetError::DuplicateName { line: 3, .. })
+        ));
+
+        let malformed = "name,forward,seq,index
+nCoV-2019_1_LEFT,yes,ACCAACCAACTTTCGATCTCTTGT,30
+";
+        match PrimerSet::from_reader(malformed.as_bytes(), None) {
+            Err(PrimerSetError::Malformed { line: 2, msg }) => {
+                assert!(!msg.contains("DeserializeError"), "{}", msg)
+            }
+            r => panic!("expected a malformed line, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_degenerate() {
+        let scheme = "name,forward,seq,index
+mpox_1_LEFT,true,ACCAACCAACTTTCGATCTCTTGT,30
+mpox_1_RIGHT,false,CATCTTTAAGATGTTGACGTGCCTC,385
+mpox_2_LEFT,true,CTGTTTTACRGGTTCGCGAYGT,320
+mpox_2_RIGHT,false,TAAGGATCAGTGCCAAGCTCGT,704
+";
+        let primers = PrimerSet::from_reader(scheme.as_bytes(), None).unwrap();
+
+        let exact: Seq<Dna> = "ACCAACCAACTTTCGATCTCTTGTAAAA".try_into().unwrap();
+        assert_eq!(primers.get(&exact).unwrap().0.name, "mpox_1_LEFT");
+
+        for read in ["CTGTTTTACAGGTTCGCGACGTAAAA", "CTGTTTTACGGGTTCGCGATGTAAAA"] {
+            let read: Seq<Dna> = read.try_into().unwrap();
+            assert_eq!(primers.get(&read).unwrap().0.name, "mpox_2_LEFT");
+        }
+
+        let incompatible: Seq<Dna> = "CTGTTTTACTGGTTCGCGACGTAAAA".try_into().unwrap();
+        assert_eq!(primers.get(&incompatible), None);
+    }
+
+    #[test]
+    fn test_mismatch_tolerance() {
+        let primers = PrimerSet::from_reader(ARTIC_V3.as_bytes(), None)
+            .unwrap()
+            .with_max_mismatches(3);
+
+        // nCoV-2019_1_LEFT with two sequencing errors
+        let read: Seq<Dna> = "ACCAACGAACTTTCGATCTCTAGTAGATCTGTTC".try_into().unwrap();
+        match primers.find(&read) {
+            PrimerMatch::Inexact(p, d, 0) => {
+                assert_eq!(p.name, "nCoV-2019_1_LEFT");
+                assert_eq!(d, 2);
+            }
+            m => panic!("expected an inexact match, got {:?}", m),
+        }
+
+        let exact = PrimerSet::from_reader(ARTIC_V3.as_bytes(), None).unwrap();
+        assert_eq!(exact.find(&read), PrimerMatch::Unmatched);
+
+        let mut stats = Stats::default();
+        stats.record_match(&primers.find(&read), &read);
+        stats.record_match(&primers.find(&read), &read);
+        let top = stats.top_inexact(1);
+        assert_eq!(top[0].0, "nCoV-2019_1_LEFT");
+        assert_eq!(top[0].2, 2);
+    }
+
+    #[test]
+    fn test_ambiguous_match() {
+        let scheme = "name,forward,seq,index
+a_1_LEFT,true,ACGTACGTACGTACGTACGA,0
+a_1_RIGHT,false,ACGTACGTACGTACGTACTT,300
+";
+        let primers = PrimerSet::from_reader(scheme.as_bytes(), None)
+            .unwrap()
+            .with_max_mismatches(3);
+        let read: Seq<Dna> = "ACGTACGTACGTACGTACTAGGGG".try_into().unwrap();
+        assert!(matches!(primers.find(&read), PrimerMatch::Ambiguous(_, 1)));
+        assert_eq!(primers.get(&read), None);
+    }
+
+    #[test]
+    fn test_primer_offset() {
+        let primers = PrimerSet::from_reader(ARTIC_V3.as_bytes(), None).unwrap();
+        // nCoV-2019_1_LEFT behind a 6bp inline barcode
+        let read: Seq<Dna> = "GATTACACCAACCAACTTTCGATCTCTTGTAGATCTGTTC"
+            .try_into()
+            .unwrap();
+        assert_eq!(primers.get(&read), None);
+
+        let primers = primers.with_max_offset(12);
+        let (primer, offset) = primers.get(&read).unwrap();
+        assert_eq!(primer.name, "nCoV-2019_1_LEFT");
+        assert_eq!(offset, 6);
+
+        let primers = primers.with_max_mismatches(2);
+        let read: Seq<Dna> = "GATTACACCAACGAACTTTCGATCTCTTGTAGATCTGTTC"
+            .try_into()
+            .unwrap();
+        assert!(matches!(primers.find(&read), PrimerMatch::Inexact(_, 1, 6)));
+    }
+
+    #[test]
+    fn test_prefix_collision() {
+        let scheme = "name,forward,seq,index
+a_1_LEFT,true,ACGTACGTACGTACGTACGA,0
+a_1_LEFT_alt1,true,ACGTACGTACGTACGTACGATTG,0
+a_1_RIGHT,false,TTGCATGCATGCATGCATGCA,300
+";
+        let primers = PrimerSet::from_reader(scheme.as_bytes(), None).unwrap();
+        assert_eq!(
+            primers.collisions,
+            vec![("a_1_LEFT".to_string(), "a_1_LEFT_alt1".to_string())]
+        );
+
+        let read: Seq<Dna> = "ACGTACGTACGTACGTACGATTGCCCC".try_into().unwrap();
+        assert_eq!(primers.get(&read).unwrap().0.name, "a_1_LEFT_alt1");
+        let read: Seq<Dna> = "ACGTACGTACGTACGTACGACCCCCCC".try_into().unwrap();
+        assert_eq!(primers.get(&read).unwrap().0.name, "a_1_LEFT");
+    }
+
+    #[test]
+    fn test_cross_pool() {
+        let primers = PrimerSet::from_bed(BED.as_bytes(), None).unwrap();
+        let p = |name: &str| primers.iter().find(|p| p.name == name).unwrap();
+
+        assert!(primers.compatible_pair(p("nCoV-2019_1_LEFT"), p("nCoV-2019_1_RIGHT")));
+        assert!(!primers.compatible_pair(p("nCoV-2019_1_LEFT"), p("nCoV-2019_2_RIGHT")));
+        // same pool, but not a target
+        assert!(primers.compatible_pair(p("nCoV-2019_1_LEFT"), p("nCoV-2019_7_LEFT_alt0")));
+        assert!(!primers.on_target(p("nCoV-2019_1_LEFT"), p("nCoV-2019_7_LEFT_alt0")));
+
+        let r1: Seq<Dna> = "ACCAACCAACTTTCGATCTCTTGTAGATCTGTTCTCTAAACGAAC"
+            .try_into()
+            .unwrap();
+        let r2: Seq<Dna> = "TAAGGATCAGTGCCAAGCTCGTCGCCCTATTAGCTTAGCTTCCAAAG"
+            .try_into()
+            .unwrap();
+        let mut stats = Stats::default();
+        assert!(matches!(
+            primers.get_amplicon(&r1, &r2, &MatingParams::default(), &mut stats),
+            Amplicon::Chimeric(_, _)
+        ));
+        assert_eq!(stats.chimeric, 1);
+        assert_eq!(
+            stats.top_chimeras(1),
+            vec![(
+                &(
+                    "nCoV-2019_1_LEFT".to_string(),
+                    "nCoV-2019_2_RIGHT".to_string()
+                ),
+                1
+            )]
+        );
+        assert_eq!(stats.pool_pairs.get(&(Some(1), Some(2))), Some(&1));
+    }
+
+    #[test]
+    fn test_spurious_pairs() {
+        let scheme = "name,forward,seq,index
+a_1_LEFT,true,ACCAACCAACTTTCGATCTCTTGT,30
+a_1_RIGHT,false,CATCTTTAAGATGTTGACGTGCCTC,385
+a_2_LEFT,true,CTGTTTTACAGGTTCGCGACGT,320
+a_2_RIGHT,false,TAAGGATCAGTGCCAAGCTCGT,704
+";
+        let primers = PrimerSet::from_reader(scheme.as_bytes(), None).unwrap();
+        let p = |name: &str| primers.iter().find(|p| p.name == name).unwrap();
+
+        assert!(primers.on_target(p("a_1_LEFT"), p("a_1_RIGHT")));
+        assert!(primers.on_target(p("a_2_RIGHT"), p("a_2_LEFT")));
+        assert!(!primers.on_target(p("a_1_LEFT"), p("a_2_RIGHT")));
+        assert!(!primers.on_target(p("a_1_LEFT"), p("a_1_LEFT")));
+
+        // targets with unknown pools pass the chimera check
+        let r1: Seq<Dna> = "ACCAACCAACTTTCGATCTCTTGTAGATCTGTTCTCTAAACGAAC"
+            .try_into()
+            .unwrap();
+        let r2: Seq<Dna> = "TAAGGATCAGTGCCAAGCTCGTCGCCCTATTAGCTTAGCTTCCAAAG"
+            .try_into()
+            .unwrap();
+        let mut stats = Stats::default();
+        assert!(matches!(
+            primers.get_amplicon(&r1, &r2, &MatingParams::default(), &mut stats),
+            Amplicon::Spurious(_, _)
+        ));
+        assert!(matches!(
+            primers.get_amplicon(&r2, &r1, &MatingParams::default(), &mut stats),
+            Amplicon::Spurious(_, _)
+        ));
+        assert_eq!(
+            stats.top_spurious(1),
+            vec![(&("a_1_LEFT".to_string(), "a_2_RIGHT".to_string()), 2)]
+        );
+    }
+
+    #[test]
+    fn test_implausible_span() {
+        let scheme = "name,forward,seq,index
+a_1_LEFT,true,ACCAACCAACTTTCGATCTCTTGT,30
+a_1_RIGHT,false,CATCTTTAAGATGTTGACGTGCCTC,385
+a_2_LEFT,true,CTGTTTTACAGGTTCGCGACGT,320
+a_2_RIGHT,false,GGTTTCCTTAGGCACTTAGGCA,704
+a_3_LEFT,true,TAAGGATCAGTGCCAAGCTCGT,600
+a_3_RIGHT,false,GTTCGTTTAGAGAACAGATCTA,10600
+";
+        let primers = PrimerSet::from_reader(scheme.as_bytes(), None).unwrap();
+        let p = |name: &str| primers.iter().find(|p| p.name == name).unwrap();
+
+        assert!(primers.on_target(p("a_1_LEFT"), p("a_1_RIGHT")));
+        assert!(primers.on_target(p("a_2_LEFT"), p("a_2_RIGHT")));
+        // a 10 kb target among ~400bp amplicons
+        assert!(primers.compatible_pair(p("a_3_LEFT"), p("a_3_RIGHT")));
+        assert!(!primers.on_target(p("a_3_LEFT"), p("a_3_RIGHT")));
+
+        // a_3_LEFT, and the reverse complement of a_3_RIGHT
+        let r1: Seq<Dna> = "TAAGGATCAGTGCCAAGCTCGTCGCCCTATTAGCTTAGCTTCCAAAG"
+            .try_into()
+            .unwrap();
+        let r2: Seq<Dna> = "GTTCGTTTAGAGAACAGATCTACAAGAGATCGAAAGTTGGTTGGT"
+            .try_into()
+            .unwrap();
+        let mut stats = Stats::default();
+        assert!(matches!(
+            primers.get_amplicon(&r1, &r2, &MatingParams::default(), &mut stats),
+            Amplicon::Spurious(_, _)
+        ));
+        assert_eq!(
+            stats
+                .spurious
+                .get(&("a_3_LEFT".to_string(), "a_3_RIGHT".to_string())),
+            Some(&1)
+        );
+    }
+
+    #[test]
+    fn test_segmented_reference() {
+        let bed = "seg4\t2\t12\tflu_1_LEFT\t1\t+\tACGTACGTAC
+seg4\t30\t40\tflu_1_RIGHT\t1\t-\tGGGGCCCCAA
+seg7\t0\t10\tflu_2_LEFT\t1\t+\tTTGGTTGGAA
+seg7\t20\t30\tflu_2_RIGHT\t1\t-\tCCAACCAACC
+";
+        let mut reference = Reference::new();
+        reference.push(
+            "seg4",
+            "AAACGTACGTACAAAAAAAAAAAAAAAAAATTGGGGCCCCAAA"
+                .try_into()
+                .unwrap(),
+        );
+        reference.push("seg7", "TTGGTTGGAACCCCCCCCCCGGTTGGTTGG".try_into().unwrap());
+
+        let primers = PrimerSet::from_bed(bed.as_bytes(), Some(&reference)).unwrap();
+        let seg4 = primers.targets.get("flu_1").unwrap();
+        assert_eq!(seg4.chrom.as_deref(), Some("seg4"));
+        assert_eq!(seg4.seq.as_ref().unwrap().len(), 38);
+        let seg7 = primers.targets.get("flu_2").unwrap();
+        assert_eq!(seg7.chrom.as_deref(), Some("seg7"));
+        assert_eq!(seg7.seq.as_ref().unwrap().len(), 30);
+
+        let p = |name: &str| primers.iter().find(|p| p.name == name).unwrap();
+        assert!(!primers.compatible_pair(p("flu_1_LEFT"), p("flu_2_RIGHT")));
+
+        let mut missing = Reference::new();
+        missing.push("seg4", "ACGT".try_into().unwrap());
+        assert!(matches!(
+            PrimerSet::from_bed(bed.as_bytes(), Some(&missing)),
+            Err(PrimerSetError::UnknownChrom { .. }) | Err(PrimerSetError::OutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_primer_dimer() {
+        let primers = PrimerSet::from_bed(BED.as_bytes(), None).unwrap();
+
+        // nCoV-2019_1_LEFT, 4bp, revcomp(nCoV-2019_1_RIGHT), adapter
+        let r1: Seq<Dna> =
+            "ACCAACCAACTTTCGATCTCTTGTACGTGAGGCACGTCAACATCTTAAAGATGAGATCGGAAGAGCACACG"
+                .try_into()
+                .unwrap();
+        let r2: Seq<Dna> =
+            "CATCTTTAAGATGTTGACGTGCCTCACGTACAAGAGATCGAAAGTTGGTTGGTAGATCGGAAGAGCGTCGTG"
+                .try_into()
+                .unwrap();
+        let mut stats = Stats::default();
+        assert!(matches!(
+            primers.get_amplicon(&r1, &r2, &MatingParams::default(), &mut stats),
+            Amplicon::Dimer(_, _)
+        ));
+        assert_eq!(
+            stats.top_dimers(1),
+            vec![(
+                &(
+                    "nCoV-2019_1_LEFT".to_string(),
+                    "nCoV-2019_1_RIGHT".to_string()
+                ),
+                1
+            )]
+        );
+    }
+
+    #[test]
+    fn test_primer_read_through() {
+        let primers = PrimerSet::from_bed(BED.as_bytes(), None).unwrap();
+
+        // nCoV-2019_1_LEFT, 3bp, adapter, and the reverse complement of the
+        // same product in the other mate
+        let r1: Seq<Dna> = "ACCAACCAACTTTCGATCTCTTGTGACAGATCGGAAGAGCACACGTCTGAACTCCAGTCAC"
+            .try_into()
+            .unwrap();
+        let r2: Seq<Dna> = "GTCACAAGAGATCGAAAGTTGGTTGGTAGATCGGAAGAGCGTCGTGTAGGGAAAGAGTGT"
+            .try_into()
+            .unwrap();
+        let mut stats = Stats::default();
+        for (a, b) in [(&r1, &r2), (&r2, &r1)] {
+            assert!(matches!(
+                primers.get_amplicon(a, b, &MatingParams::default(), &mut stats),
+                Amplicon::Dimer(_, _)
+            ));
+        }
+        assert_eq!(
+            stats.top_dimers(1),
+            vec![(
+                &(
+                    "nCoV-2019_1_LEFT".to_string(),
+                    "nCoV-2019_1_LEFT".to_string()
+                ),
+                2
+            )]
+        );
+        assert_eq!(stats.off_target, 0);
+
+        // a mate that does not read back into the primer is still discarded
+        let r2: Seq<Dna> = "TTGGCTAGCATCGATCAGTCGGATCGACTAGCTTTTGGAACGTTACGATGCAGTTCAGTA"
+            .try_into()
+            .unwrap();
+        assert!(matches!(
+            primers.get_amplicon(&r1, &r2, &MatingParams::default(), &mut stats),
+            Amplicon::Discarded
+        ));
+        assert_eq!(stats.off_target, 1);
+    }
+
+    #[test]
+    fn test_insert_coordinates() {
+        let v3 = PrimerSet::from_reader(ARTIC_V3.as_bytes(), None).unwrap();
+        let target = v3.targets.get("nCoV-2019_1").unwrap();
+        assert_eq!((target.insert_start, target.insert_end), (54, 385));
+
+        // nCoV-2019_14_LEFT_alt4 starts further out, the insert begins after nCoV-2019_14_LEFT
+        let target = v3.targets.get("nCoV-2019_14").unwrap();
+        assert!(target.insert_start > target.start + 24);
+    }
+
+    #[test]
+    fn test_trimmed_amplicon() {
+        let primers = PrimerSet::from_bed(BED.as_bytes(), None).unwrap();
+        let p = |name: &str| primers.iter().find(|p| p.name == name).unwrap();
+        let seq: Seq<Dna> = "ACCAACCAACTTTCGATCTCTTGTTTTTGAGGCACGTCAACATCTTAAAGATG"
+            .try_into()
+            .unwrap();
+        let amplicon = Amplicon::Merged(
+            Orientation::F1R2,
+            p("nCoV-2019_1_LEFT"),
+            p("nCoV-2019_1_RIGHT"),
+            seq,
+            vec![30; 53],
+        );
+        assert_eq!(amplicon.trimmed().unwrap().to_string(), "TTTT");
+        assert_eq!(
+            Amplicon::Paired(
+                Orientation::F1R2,
+                p("nCoV-2019_1_LEFT"),
+                p("nCoV-2019_1_RIGHT")
+            )
+            .trimmed(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_saved_primer_set() {
+        let bed = "seg4\t2\t12\tflu_1_LEFT\t1\t+\tACGTACGTAC
+seg4\t30\t40\tflu_1_RIGHT\t1\t-\tGGGGCCCCAA
+seg4\t2\t14\tflu_1_LEFT_alt1\t1\t+\tACGTACGTACAA
+";
+        let mut reference = Reference::new();
+        reference.push(
+            "seg4",
+            "AAACGTACGTACAAAAAAAAAAAAAAAAAATTGGGGCCCCAAA"
+                .try_into()
+                .unwrap(),
+        );
+        let primers = PrimerSet::from_bed(bed.as_bytes(), Some(&reference))
+            .unwrap()
+            .with_max_mismatches(2);
+
+        let mut json = Vec::new();
+        primers.to_json(&mut json).unwrap();
+        let mut binary = Vec::new();
+        primers.to_binary(&mut binary).unwrap();
+        assert!(binary.len() < json.len());
+
+        for loaded in [
+            PrimerSet::from_json(json.as_slice()).unwrap(),
+            PrimerSet::from_binary(binary.as_slice()).unwrap(),
+        ] {
+            assert_eq!(loaded.targets, primers.targets);
+            assert_eq!(loaded.plen, primers.plen);
+            assert_eq!(loaded.max_amplicon, primers.max_amplicon);
+            assert_eq!(loaded.collisions, primers.collisions);
+            assert_eq!(loaded.max_mismatches, 2);
+            assert!(loaded.iter().eq(primers.iter()));
+
+            let read: Seq<Dna> = "ACGTACGTACAATTTT".try_into().unwrap();
+            assert_eq!(loaded.get_exact(&read).unwrap().name, "flu_1_LEFT_alt1");
+        }
+
+        assert_eq!(
+            PrimerFormat::detect(std::str::from_utf8(&json).unwrap()),
+            PrimerFormat::Json
+        );
+        assert!(matches!(
+            PrimerSet::from_binary(json.as_slice()),
+            Err(PrimerSetError::Saved(_))
+        ));
+    }
+
+    #[test]
+    fn test_alt_primers() {
+        assert_eq!(
+            canonical_name("nCoV-2019_14_LEFT_alt4"),
+            "nCoV-2019_14_LEFT"
+        );
+        assert_eq!(canonical_name("SARS-CoV-2_1_RIGHT_1"), "SARS-CoV-2_1_RIGHT");
+        assert_eq!(canonical_name("nCoV-2019_1_LEFT"), "nCoV-2019_1_LEFT");
+
+        let bed = "ref\t2\t12\ta_1_LEFT\t1\t+\tCATCGTAGCT
+ref\t0\t10\ta_1_LEFT_alt1\t1\t+\tGGCATCGTAG
+ref\t30\t40\ta_1_RIGHT\t1\t-\tCATGGTCAAC
+";
+        let primers = PrimerSet::from_bed(bed.as_bytes(), None).unwrap();
+        assert_eq!(primers.alternates("a_1_LEFT").count(), 2);
+        let p = |name: &str| primers.iter().find(|p| p.name == name).unwrap();
+        assert!(p("a_1_LEFT_alt1").is_alt());
+
+        let canonical = Amplicon::Merged(
+            Orientation::F1R2,
+            p("a_1_LEFT"),
+            p("a_1_RIGHT"),
+            "CATCGTAGCTTACGGATCCATGCAGTCAGTTGACCATG".try_into().unwrap(),
+            vec![30; 38],
+        );
+        let alt = Amplicon::Merged(
+            Orientation::F1R2,
+            p("a_1_LEFT_alt1"),
+            p("a_1_RIGHT"),
+            "GGCATCGTAGCTTACGGATCCATGCAGTCAGTTGACCATG"
+                .try_into()
+                .unwrap(),
+            vec![30; 40],
+        );
+        let (t1, s1) = primers.amplicon_insert(&canonical).unwrap();
+        let (t2, s2) = primers.amplicon_insert(&alt).unwrap();
+        assert_eq!(t1.name, "a_1");
+        assert_eq!(t1, t2);
+        assert_eq!(s1.to_string(), "TACGGATCCATGCAGTCA");
+        assert_eq!(s1.to_string(), s2.to_string());
+
+        let mut stats = Stats::default();
+        stats.record_usage(p("a_1_LEFT"));
+        stats.record_usage(p("a_1_LEFT_alt1"));
+        stats.record_usage(p("a_1_LEFT_alt1"));
+        stats.record_usage(p("a_1_RIGHT"));
+        assert_eq!(
+            stats.alt_usage(),
+            vec![
+                ("a_1_LEFT", "a_1_LEFT", 1),
+                ("a_1_LEFT", "a_1_LEFT_alt1", 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_short_amplicon() {
+        let scheme = "name,forward,seq,index
+a_1_LEFT,true,TGGAGGACACATTAATAATT,10
+a_1_RIGHT,false,AGCAACAGCTAAATTGTGAC,50
+";
+        let primers = PrimerSet::from_reader(scheme.as_bytes(), None).unwrap();
+        let mut stats = Stats::default();
+
+        // a 60bp amplicon sequenced with 80bp reads
+        let r1: Seq<Dna> =
+            "TGGAGGACACATTAATAATTTGCTCACTCCCTATATTATTGTCACAATTTAGCTGTTGCTAGATCGGAAGAGCACACGTC"
+                .try_into()
+                .unwrap();
+        let r2: Seq<Dna> =
+            "AGCAACAGCTAAATTGTGACAATAATATAGGGAGTGAGCAAATTATTAATGTGTCCTCCAAGATCGGAAGAGCGTCGTGT"
+                .try_into()
+                .unwrap();
+
+        match primers.get_amplicon(&r1, &r2, &MatingParams::default(), &mut stats) {
+            Amplicon::Merged(Orientation::F1R2, p1, p2, seq, _) => {
+                assert_eq!(p1.name, "a_1_LEFT");
+                assert_eq!(p2.name, "a_1_RIGHT");
+                assert_eq!(
+                    seq.to_string(),
+                    "TGGAGGACACATTAATAATTTGCTCACTCCCTATATTATTGTCACAATTTAGCTGTTGCT"
+                );
+            }
+            amplicon => panic!("expected a merged amplicon, got {:?}", amplicon),
+        }
+    }
+
+    #[test]
+    fn test_mating_stats() {
+        let scheme = "name,forward,seq,index
+x_1_LEFT,true,GCTAAAGACAATTACATAAC,0
+x_1_RIGHT,false,CAAGTAAAGGCGTATGCATC,80
+";
+        let primers = PrimerSet::from_reader(scheme.as_bytes(), None).unwrap();
+        let mut stats = Stats::default();
+
+        // a 100bp amplicon sequenced with 70bp reads overlaps by 40bp
+        let r1: Seq<Dna> = "GCTAAAGACAATTACATAACATACACGTCAGCACGAAACTTGTTGGCCCAGTGTGAATCGCTTAAGGGTT"
+            .try_into()
+            .unwrap();
+        let r2: Seq<Dna> = "CAAGTAAAGGCGTATGCATCACACTTACTTAACCCTTAAGCGATTCACACTGGGCCAACAAGTTTCGTGC"
+            .try_into()
+            .unwrap();
+        let params = MatingParams::default();
+        assert!(matches!(
+            primers.get_amplicon(&r1, &r2, &params, &mut stats),
+            Amplicon::Merged(Orientation::F1R2, _, _, _, _)
+        ));
+        // the same pair with the mates swapped
+        assert!(matches!(
+            primers.get_amplicon(&r2, &r1, &params, &mut stats),
+            Amplicon::Merged(Orientation::R2F1, _, _, _, _)
+        ));
+        // too short to overlap, the best candidate is 30bp with 19 mismatches
+        // and 30 bases from the expected overlap
+        assert!(matches!(
+            primers.get_amplicon(&r1[..40], &r2[..40], &params, &mut stats),
+            Amplicon::Paired(Orientation::F1R2, a, b) if a.name == "x_1_LEFT" && b.name == "x_1_RIGHT"
+        ));
+        // no candidate overlap is long enough to score
+        let long = MatingParams {
+            min_overlap: 50,
+            ..params
+        };
+        assert!(matches!(
+            primers.get_amplicon(&r1[..40], &r2[..40], &long, &mut stats),
+            Amplicon::Paired(Orientation::F1R2, _, _)
+        ));
+
+        assert_eq!(stats.mated, 2);
+        assert_eq!(stats.insert_sizes["x_1"], BTreeMap::from([(100, 2)]));
+        // 40 matching bases at the expected overlap
+        assert_eq!(
+            stats.overlap_scores["x_1"],
+            BTreeMap::from([(-38, 1), (40, 2)])
+        );
+        assert_eq!(stats.unscored["x_1"], 1);
+
+        // the hamming rate objective is binned by percentage of mismatches
+        let mut stats = Stats::default();
+        let rate = MatingParams {
+            objective: Objective::HammingRate,
+            ..params
+        };
+        primers.get_amplicon(&r1, &r2, &rate, &mut stats);
+        primers.get_amplicon(&r1[..40], &r2[..40], &rate, &mut stats);
+        assert_eq!(
+            stats.overlap_scores["x_1"],
+            BTreeMap::from([(0, 1), (63, 1)])
+        );
+    }
+
+    #[test]
+    fn test_trimmed_reads() {
+        let scheme = "name,forward,seq,index
+a_1_LEFT,true,TGGAGGACACATTAATAATT,10
+a_1_RIGHT,false,AGCAACAGCTAAATTGTGAC,50
+";
+        let primers = PrimerSet::from_reader(scheme.as_bytes(), None).unwrap();
+        let mut stats = Stats::default();
+        let params = MatingParams::default();
+        let r1: Seq<Dna> =
+            "TGGAGGACACATTAATAATTTGCTCACTCCCTATATTATTGTCACAATTTAGCTGTTGCTAGATCGGAAGAGCACACGTC"
+                .try_into()
+                .unwrap();
+        let r2: Seq<Dna> =
+            "AGCAACAGCTAAATTGTGACAATAATATAGGGAGTGAGCAAATTATTAATGTGTCCTCCAAGATCGGAAGAGCGTCGTGT"
+                .try_into()
+                .unwrap();
+
+        // mates trimmed down to little more than their primers
+        assert!(matches!(
+            primers.get_amplicon(&r1[..20], &r2[..25], &params, &mut stats),
+            Amplicon::Paired(Orientation::F1R2, _, _)
+        ));
+        // trimmed past the primer
+        assert!(matches!(
+            primers.get_amplicon(&r1[..12], &r2, &params, &mut stats),
+            Amplicon::Discarded
+        ));
+        // qualities that do not cover the read are ignored
+        assert!(matches!(
+            primers.get_amplicon_qual(&r1, &[30; 60], &r2, &[30; 80], &params, &mut stats),
+            Amplicon::Merged(Orientation::F1R2, _, _, _, _)
+        ));
+    }
 }
@@ -0,0 +1,206 @@
+//! Primer-site mutations in the sample
+//!
+//! Primers anneal to the template but the reads of their own amplicon start
+//! with the primer sequence, so a mutation under a primer is only visible in
+//! the consensus of an overlapping amplicon. Each primer's binding site is
+//! compared against the consensus of every other amplicon that covers it.
+
+use core::fmt;
+
+use bio_seq::prelude::*;
+
+use crate::primerset::{compatible, Primer, PrimerSet};
+
+/// Consensus sequence of one amplicon, in reference coordinates
+#[derive(Debug, Clone)]
+pub struct Consensus {
+    pub target: String,
+    pub chrom: Option<String>,
+    pub start: usize,
+    pub end: usize,
+    pub seq: Seq<Dna>,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiteMismatch {
+    /// Reference coordinate of the mismatch
+    pub position: usize,
+    /// Distance of the mismatch from the primer's 3' end
+    pub from_3prime: usize,
+    pub observed: Dna,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrimerSiteReport {
+    pub primer: String,
+    pub target: String,
+    pub mismatches: Vec<SiteMismatch>,
+    /// Number of pairs supporting the primer's own amplicon
+    pub coverage: usize,
+}
+
+impl fmt::Display for PrimerSiteReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let positions: Vec<String> = self
+            .mismatches
+            .iter()
+            .map(|m| format!("{}{:?}(3'-{})", m.position, m.observed, m.from_3prime))
+            .collect();
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.primer,
+            self.target,
+            positions.join(","),
+            self.coverage
+        )
+    }
+}
+
+fn complement(c: char) -> char {
+    match c.to_ascii_uppercase() {
+        'A' => 'T',
+        'T' => 'A',
+        'C' => 'G',
+        'G' => 'C',
+        'R' => 'Y',
+        'Y' => 'R',
+        'K' => 'M',
+        'M' => 'K',
+        'B' => 'V',
+        'V' => 'B',
+        'D' => 'H',
+        'H' => 'D',
+        c => c,
+    }
+}
+
+/// The primer as it appears on the forward strand of the reference, and its
+/// reference start coordinate
+fn forward_site(primer: &Primer) -> Option<(Seq<Iupac>, usize)> {
+    let len = primer.seq.len();
+    if primer.forward {
+        Some((primer.seq.as_str().try_into().ok()?, primer.index))
+    } else {
+        let rc: String = primer.seq.chars().rev().map(complement).collect();
+        Some((rc.as_str().try_into().ok()?, primer.index.checked_sub(len)?))
+    }
+}
+
+/// Compare every primer's binding site with the consensus of overlapping amplicons
+pub fn primer_site_report(primers: &PrimerSet, consensus: &[Consensus]) -> Vec<PrimerSiteReport> {
+    let mut reports = Vec::new();
+
+    for primer in primers.iter() {
+        let (expected, start) = match forward_site(primer) {
+            Some(site) => site,
+            None => continue,
+        };
+        let expected: &SeqSlice<Iupac> = &expected;
+        let end = start + expected.len();
+        let mut mismatches: Vec<SiteMismatch> = Vec::new();
+
+        for c in consensus {
+            if c.target == primer.target
+                || c.chrom != primer.chrom
+                || c.start > start
+                || c.end < end
+                || c.start + c.seq.len() < end
+            {
+                continue;
+            }
+            let window = &c.seq[start - c.start..end - c.start];
+            for (i, (p, b)) in expected.into_iter().zip(window.into_iter()).enumerate() {
+                if compatible(p, b) || mismatches.iter().any(|m| m.position == start + i) {
+                    continue;
+                }
+                let from_3prime = if primer.forward {
+                    expected.len() - 1 - i
+                } else {
+                    i
+                };
+                mismatches.push(SiteMismatch {
+                    position: start + i,
+                    from_3prime,
+                    observed: b,
+                });
+            }
+        }
+
+        if mismatches.is_empty() {
+            continue;
+        }
+        mismatches.sort_by_key(|m| m.position);
+
+        reports.push(PrimerSiteReport {
+            primer: primer.name.clone(),
+            target: primer.target.clone(),
+            mismatches,
+            coverage: consensus
+                .iter()
+                .filter(|c| c.target == primer.target)
+                .map(|c| c.count)
+                .sum(),
+        });
+    }
+
+    reports.sort_by(|a, b| a.primer.cmp(&b.primer));
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{primer_site_report, Consensus};
+    use crate::primerset::PrimerSet;
+    use bio_seq::prelude::*;
+
+    #[test]
+    fn test_mutation_under_primer() {
+        let scheme = "name,forward,seq,index
+a_1_LEFT,true,AAAACCCCGGGG,0
+a_1_RIGHT,false,TTTTGGGGCCCC,50
+a_2_LEFT,true,ACGTACGTACGT,30
+a_2_RIGHT,false,GGAATTCCGGAA,90
+";
+        let primers = PrimerSet::from_reader(scheme.as_bytes(), None).unwrap();
+
+        // a_1 covers the a_2_LEFT site (30..42) with a mutation at 40, and a_2
+        // agrees with the a_1_RIGHT site (50..62)
+        let a1: Seq<Dna> = "AAAACCCCGGGGTTTTTTTTTTTTTTTTTTACGTACGTACATTTTTTTTTGGGGCCCCAAAA"
+            .try_into()
+            .unwrap();
+        let a2: Seq<Dna> =
+            "ACGTACGTACGTTTTTTTTTGGGGCCCCAAAATTTTTTTTTTTTTTTTTTTTTTTTTTTTTTCCGGAATTCC"
+                .try_into()
+                .unwrap();
+
+        let consensus = vec![
+            Consensus {
+                target: "a_1".to_string(),
+                chrom: None,
+                start: 0,
+                end: 62,
+                seq: a1,
+                count: 200,
+            },
+            Consensus {
+                target: "a_2".to_string(),
+                chrom: None,
+                start: 30,
+                end: 102,
+                seq: a2,
+                count: 7,
+            },
+        ];
+
+        let report = primer_site_report(&primers, &consensus);
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].primer, "a_2_LEFT");
+        assert_eq!(report[0].coverage, 7);
+        assert_eq!(report[0].mismatches.len(), 1);
+        assert_eq!(report[0].mismatches[0].position, 40);
+        assert_eq!(report[0].mismatches[0].from_3prime, 1);
+        assert_eq!(report[0].mismatches[0].observed, Dna::A);
+    }
+}
@@ -0,0 +1,98 @@
+//! Built-in registry of standard primer schemes
+//!
+//! Schemes are embedded at compile time from `ampliconlib/schemes/`. To add a
+//! scheme, place its CSV or BED file there and add an entry to `SCHEMES`.
+
+use core::cmp::{max, min};
+
+use crate::primerset::{PrimerFormat, PrimerSet, PrimerSetError};
+
+/// Scheme names and their primer files
+pub const SCHEMES: &[(&str, &str)] = &[
+    ("artic-v3", include_str!("../schemes/artic-v3.csv")),
+    ("artic-v4.1", include_str!("../schemes/artic-v4.1.csv")),
+];
+
+/// Names of the built-in schemes
+pub fn names() -> impl Iterator<Item = &'static str> {
+    SCHEMES.iter().map(|(name, _)| *name)
+}
+
+/// Summary of a scheme for listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemeSummary {
+    pub name: String,
+    pub amplicons: usize,
+    pub min_len: usize,
+    pub max_len: usize,
+}
+
+impl PrimerSet {
+    /// Load one of the built-in schemes by name, eg. `artic-v4.1`
+    pub fn builtin(name: &str) -> Result<PrimerSet, PrimerSetError> {
+        let (_, src) = SCHEMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .ok_or_else(|| PrimerSetError::UnknownScheme(name.to_string()))?;
+
+        match PrimerFormat::detect(src) {
+            PrimerFormat::Bed => PrimerSet::from_bed(src.as_bytes(), None),
+            PrimerFormat::Csv => PrimerSet::from_reader(src.as_bytes(), None),
+            PrimerFormat::Json => PrimerSet::from_json(src.as_bytes()),
+        }
+    }
+
+    /// Number of amplicons and the range of their lengths
+    pub fn summary(&self, name: &str) -> SchemeSummary {
+        let mut min_len = usize::MAX;
+        let mut max_len = 0;
+        for target in self.targets.values() {
+            let len = target.end - target.start;
+            min_len = min(min_len, len);
+            max_len = max(max_len, len);
+        }
+        SchemeSummary {
+            name: name.to_string(),
+            amplicons: self.targets.len(),
+            min_len: if self.targets.is_empty() { 0 } else { min_len },
+            max_len,
+        }
+    }
+}
+
+/// Summaries of every built-in scheme
+pub fn summaries() -> Result<Vec<SchemeSummary>, PrimerSetError> {
+    names()
+        .map(|name| Ok(PrimerSet::builtin(name)?.summary(name)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{names, summaries};
+    use crate::primerset::{PrimerSet, PrimerSetError};
+
+    #[test]
+    fn test_builtin_schemes() {
+        for name in names() {
+            let scheme = PrimerSet::builtin(name).unwrap();
+            assert!(scheme.iter().all(|p| p.pool.is_some()), "{}", name);
+        }
+
+        // ARTIC amplicons alternate between the two pools
+        let v3 = PrimerSet::builtin("artic-v3").unwrap();
+        let p = |name: &str| v3.iter().find(|p| p.name == name).unwrap();
+        assert_eq!(p("nCoV-2019_1_LEFT").pool, Some(1));
+        assert_eq!(p("nCoV-2019_2_RIGHT").pool, Some(2));
+        assert!(!v3.compatible_pair(p("nCoV-2019_1_LEFT"), p("nCoV-2019_2_RIGHT")));
+        assert!(matches!(
+            PrimerSet::builtin("artic-v0"),
+            Err(PrimerSetError::UnknownScheme(_))
+        ));
+
+        let summaries = summaries().unwrap();
+        let v41 = summaries.iter().find(|s| s.name == "artic-v4.1").unwrap();
+        assert_eq!(v41.amplicons, 99);
+        assert!(v41.min_len > 300 && v41.max_len < 500);
+    }
+}
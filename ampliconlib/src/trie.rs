@@ -0,0 +1,141 @@
+//! Prefix trie over primer sequences
+//!
+//! Every primer is matched over its whole length. Primers may contain IUPAC
+//! ambiguity codes, so matching a read follows every edge that is compatible
+//! with the read base.
+
+use bio_seq::prelude::*;
+
+use crate::primerset::compatible;
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    children: Vec<(Iupac, usize)>,
+    /// Primers that end at this node
+    terminal: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PrimerTrie {
+    nodes: Vec<Node>,
+}
+
+impl Default for PrimerTrie {
+    fn default() -> Self {
+        PrimerTrie::new()
+    }
+}
+
+impl PrimerTrie {
+    pub fn new() -> Self {
+        PrimerTrie {
+            nodes: vec![Node::default()],
+        }
+    }
+
+    /// Insert a primer by id and return the ids of primers that it collides with,
+    /// ie. primers that are a prefix of it or that it is a prefix of.
+    pub fn insert(&mut self, seq: &SeqSlice<Iupac>, id: usize) -> Vec<usize> {
+        let mut collisions = Vec::new();
+        let mut node = 0;
+
+        for base in seq {
+            let next = self.nodes[node]
+                .children
+                .iter()
+                .find(|(b, _)| *b == base)
+                .map(|(_, child)| *child);
+
+            node = match next {
+                Some(child) => child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node::default());
+                    self.nodes[node].children.push((base, child));
+                    child
+                }
+            };
+            collisions.extend(&self.nodes[node].terminal);
+        }
+
+        // primers that extend this one
+        let mut stack: Vec<usize> = self.nodes[node].children.iter().map(|(_, c)| *c).collect();
+        while let Some(n) = stack.pop() {
+            collisions.extend(&self.nodes[n].terminal);
+            stack.extend(self.nodes[n].children.iter().map(|(_, c)| *c));
+        }
+
+        self.nodes[node].terminal.push(id);
+        collisions
+    }
+
+    /// Ids of every primer that matches the start of a read, shortest first
+    pub fn matches(&self, read: &SeqSlice<Dna>) -> Vec<usize> {
+        let mut hits = Vec::new();
+        let mut active = vec![0];
+
+        for base in read {
+            let mut next = Vec::new();
+            for node in active {
+                for (code, child) in &self.nodes[node].children {
+                    if compatible(*code, base) {
+                        hits.extend(&self.nodes[*child].terminal);
+                        next.push(*child);
+                    }
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            active = next;
+        }
+        hits
+    }
+
+    /// The longest primer that matches the start of a read
+    pub fn longest(&self, read: &SeqSlice<Dna>) -> Option<usize> {
+        self.matches(read).last().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrimerTrie;
+    use bio_seq::prelude::*;
+
+    #[test]
+    fn test_full_length_match() {
+        let short: Seq<Iupac> = "ACGTACGTAC".try_into().unwrap();
+        let long: Seq<Iupac> = "ACGTACGTACGGTT".try_into().unwrap();
+        let other: Seq<Iupac> = "ACGTACGTAG".try_into().unwrap();
+
+        let mut trie = PrimerTrie::new();
+        assert!(trie.insert(&short, 0).is_empty());
+        assert_eq!(trie.insert(&long, 1), vec![0]);
+        assert!(trie.insert(&other, 2).is_empty());
+
+        let read: Seq<Dna> = "ACGTACGTACGGTTAAAA".try_into().unwrap();
+        assert_eq!(trie.matches(&read), vec![0, 1]);
+        assert_eq!(trie.longest(&read), Some(1));
+
+        let read: Seq<Dna> = "ACGTACGTACGCTTAAAA".try_into().unwrap();
+        assert_eq!(trie.longest(&read), Some(0));
+
+        let read: Seq<Dna> = "ACGTACGTTTTTTTAAAA".try_into().unwrap();
+        assert_eq!(trie.longest(&read), None);
+    }
+
+    #[test]
+    fn test_degenerate_edges() {
+        let primer: Seq<Iupac> = "ACGRACNTAC".try_into().unwrap();
+        let mut trie = PrimerTrie::new();
+        trie.insert(&primer, 0);
+
+        for read in ["ACGAACATACTT", "ACGGACTTACTT"] {
+            let read: Seq<Dna> = read.try_into().unwrap();
+            assert_eq!(trie.longest(&read), Some(0));
+        }
+        let read: Seq<Dna> = "ACGCACATACTT".try_into().unwrap();
+        assert_eq!(trie.longest(&read), None);
+    }
+}
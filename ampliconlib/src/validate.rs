@@ -0,0 +1,276 @@
+//! In-silico PCR of a primer scheme against its reference
+//!
+//! Every primer is searched for on both strands of every reference record,
+//! allowing mismatches. The resulting binding sites are checked against the
+//! coordinates declared in the scheme and against the targets they should
+//! amplify.
+
+use core::fmt;
+
+use bio_seq::prelude::*;
+
+use crate::primerset::{hamming, Primer, PrimerSet, Reference, Target};
+
+/// A place in the reference where a primer anneals
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindingSite {
+    pub primer: String,
+    pub chrom: String,
+    pub start: usize,
+    pub end: usize,
+    /// Whether the primer binds as written (`+`) or reverse complemented (`-`)
+    pub forward: bool,
+    pub mismatches: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// The primer does not bind anywhere in the reference
+    Unbound { primer: String },
+    /// The primer binds, but not at its declared coordinate
+    Misplaced {
+        primer: String,
+        declared: usize,
+        found: Vec<usize>,
+    },
+    /// The primer binds at more than one site
+    MultipleSites { primer: String, count: usize },
+    /// No pair of the target's primers would produce an amplicon
+    NoProduct { target: String },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::Unbound { primer } => write!(f, "{}: no binding site", primer),
+            Issue::Misplaced {
+                primer,
+                declared,
+                found,
+            } => write!(
+                f,
+                "{}: declared at {} but binds at {:?}",
+                primer, declared, found
+            ),
+            Issue::MultipleSites { primer, count } => {
+                write!(f, "{}: binds at {} sites", primer, count)
+            }
+            Issue::NoProduct { target } => write!(f, "{}: no amplicon would be produced", target),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    pub sites: Vec<BindingSite>,
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Find every binding site of a primer on both strands of a reference record
+pub fn binding_sites(
+    primer: &Primer,
+    seq: &SeqSlice<Iupac>,
+    chrom: &str,
+    reference: &SeqSlice<Dna>,
+    max_mismatches: usize,
+) -> Vec<BindingSite> {
+    let mut sites = Vec::new();
+    let len = seq.len();
+    if len > reference.len() {
+        return sites;
+    }
+
+    for i in 0..=reference.len() - len {
+        if let Some(d) = hamming(seq, &reference[i..], max_mismatches) {
+            sites.push(BindingSite {
+                primer: primer.name.clone(),
+                chrom: chrom.to_string(),
+                start: i,
+                end: i + len,
+                forward: true,
+                mismatches: d,
+            });
+        }
+    }
+
+    let rc = reference.revcomp();
+    for j in 0..=rc.len() - len {
+        if let Some(d) = hamming(seq, &rc[j..], max_mismatches) {
+            let end = rc.len() - j;
+            sites.push(BindingSite {
+                primer: primer.name.clone(),
+                chrom: chrom.to_string(),
+                start: end - len,
+                end,
+                forward: false,
+                mismatches: d,
+            });
+        }
+    }
+
+    sites
+}
+
+fn chrom_name<'a>(chrom: &'a Option<String>, reference: &'a Reference) -> &'a str {
+    match chrom {
+        Some(chrom) => chrom,
+        None => reference.names().next().unwrap_or(""),
+    }
+}
+
+/// The coordinate a primer declares: the start of a left primer or the end of a right primer
+fn declared(site: &BindingSite, primer: &Primer) -> usize {
+    if primer.forward {
+        site.start
+    } else {
+        site.end
+    }
+}
+
+fn produces(target: &Target, left: &[&BindingSite], right: &[&BindingSite]) -> bool {
+    let max_len = 2 * (target.end - target.start);
+    left.iter().any(|l| {
+        right
+            .iter()
+            .any(|r| l.chrom == r.chrom && l.start < r.end && r.end - l.start <= max_len)
+    })
+}
+
+impl PrimerSet {
+    /// Search the reference for every primer and report disagreements with the scheme
+    pub fn validate(&self, reference: &Reference, max_mismatches: usize) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let mut all_sites: Vec<BindingSite> = Vec::new();
+
+        for (seq, primer) in &self.primers {
+            let mut sites = Vec::new();
+            for (chrom, record) in &reference.records {
+                sites.extend(binding_sites(primer, seq, chrom, record, max_mismatches));
+            }
+
+            let chrom = chrom_name(&primer.chrom, reference);
+            // sites where the primer would extend in its declared direction
+            let oriented: Vec<&BindingSite> = sites
+                .iter()
+                .filter(|s| s.chrom == chrom && s.forward == primer.forward)
+                .collect();
+
+            if sites.is_empty() {
+                report.issues.push(Issue::Unbound {
+                    primer: primer.name.clone(),
+                });
+            } else if !oriented.iter().any(|s| declared(s, primer) == primer.index) {
+                report.issues.push(Issue::Misplaced {
+                    primer: primer.name.clone(),
+                    declared: primer.index,
+                    found: oriented.iter().map(|s| declared(s, primer)).collect(),
+                });
+            }
+            if sites.len() > 1 {
+                report.issues.push(Issue::MultipleSites {
+                    primer: primer.name.clone(),
+                    count: sites.len(),
+                });
+            }
+
+            all_sites.extend(sites);
+        }
+
+        let mut targets: Vec<&Target> = self.targets.values().collect();
+        targets.sort_by_key(|t| (t.chrom.clone(), t.start));
+
+        for target in targets {
+            let chrom = chrom_name(&target.chrom, reference);
+            let sites_for = |forward: bool| -> Vec<&BindingSite> {
+                all_sites
+                    .iter()
+                    .filter(|s| s.chrom == chrom && s.forward == forward)
+                    .filter(|s| {
+                        self.iter()
+                            .any(|p| p.name == s.primer && p.target == target.name)
+                    })
+                    .collect()
+            };
+            let left = sites_for(true);
+            let right = sites_for(false);
+
+            if !produces(target, &left, &right) {
+                report.issues.push(Issue::NoProduct {
+                    target: target.name.clone(),
+                });
+            }
+        }
+
+        report.sites = all_sites;
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Issue;
+    use crate::primerset::{PrimerSet, Reference};
+
+    const REFERENCE: &str = "TTTTTACCAACCAACTTTCGATCTCTTGTGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGAGGCACGTCAACATCTTAAAGATGTTTTT";
+
+    fn reference() -> Reference {
+        let mut reference = Reference::new();
+        reference.push("ref", REFERENCE.try_into().unwrap());
+        reference
+    }
+
+    #[test]
+    fn test_valid_scheme() {
+        let scheme = "name,forward,seq,index
+a_1_LEFT,true,ACCAACCAACTTTCGATCTCTTGT,5
+a_1_RIGHT,false,CATCTTTAAGATGTTGACGTGCCTC,70
+";
+        let primers = PrimerSet::from_reader(scheme.as_bytes(), None).unwrap();
+        let report = primers.validate(&reference(), 2);
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.sites.len(), 2);
+        assert!(report.sites.iter().any(|s| !s.forward && s.start == 70));
+    }
+
+    #[test]
+    fn test_scheme_issues() {
+        let scheme = "name,forward,seq,index
+a_1_LEFT,true,ACCAACCAACTTTCGATCTCTTGT,8
+a_1_RIGHT,false,CATCTTTAAGATGTTGACGTGCCTC,70
+a_2_LEFT,true,ATATATATATATATATATATAT,200
+a_2_RIGHT,false,AAAACATCTTTAAGATGTTGACG,300
+";
+        let primers = PrimerSet::from_reader(scheme.as_bytes(), None).unwrap();
+        let report = primers.validate(&reference(), 2);
+
+        assert!(report.issues.contains(&Issue::Misplaced {
+            primer: "a_1_LEFT".to_string(),
+            declared: 8,
+            found: vec![5],
+        }));
+        assert!(report.issues.contains(&Issue::Unbound {
+            primer: "a_2_LEFT".to_string(),
+        }));
+        assert!(report.issues.contains(&Issue::NoProduct {
+            target: "a_2".to_string(),
+        }));
+        assert!(!report.issues.contains(&Issue::NoProduct {
+            target: "a_1".to_string(),
+        }));
+    }
+}
@@ -4,13 +4,16 @@ use flate2::read::MultiGzDecoder;
 use core::ops::Bound::Included;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
 use store_interval_tree::{Interval, IntervalTree}; //, IntervalTreeIterator};
 
-use ampliconlib::aligner::{edit_dist, merge_bin, pp, Assembly};
+use ampliconlib::aligner::{edit_dist, merge_bin, pp, tile, Assembly};
+use ampliconlib::mating::{mask, MatingParams, Objective};
+use ampliconlib::primersites::{primer_site_report, Consensus};
+use ampliconlib::schemes;
 
 use bio_streams::fasta::Fasta;
 use bio_streams::fastq::Fastq;
@@ -20,35 +23,224 @@ use bio_seq::prelude::*;
 use ampliconlib::primerset::{
     Amplicon::{Merged, Paired},
     Orientation::{F1R2, F2R1, R1F2, R2F1},
-    PrimerSet,
+    PrimerSet, Reference, Stats,
 };
 
 #[derive(Parser)]
 struct Cli {
-    primers: PathBuf,
-    reference: PathBuf,
-    r1: PathBuf,
-    r2: PathBuf,
+    #[arg(required_unless_present = "list_schemes")]
+    reference: Option<PathBuf>,
+    #[arg(required_unless_present_any = ["list_schemes", "save_primers", "validate"])]
+    r1: Option<PathBuf>,
+    #[arg(required_unless_present_any = ["list_schemes", "save_primers", "validate"])]
+    r2: Option<PathBuf>,
+    /// Primer scheme file (CSV, BED or a saved primer set)
+    #[arg(
+        short,
+        long,
+        conflicts_with = "scheme",
+        required_unless_present_any = ["scheme", "list_schemes"]
+    )]
+    primers: Option<PathBuf>,
+    /// Built-in primer scheme, eg. artic-v4.1
+    #[arg(long)]
+    scheme: Option<String>,
+    /// List the built-in primer schemes and exit
+    #[arg(long)]
+    list_schemes: bool,
+    /// Maximum Hamming distance for inexact primer matches [default: 3, or
+    /// as saved with the primer set]
+    #[arg(short, long)]
+    mismatches: Option<usize>,
+    /// Furthest position into a read at which a primer may start [default: 0,
+    /// or as saved with the primer set]
+    #[arg(long)]
+    max_offset: Option<usize>,
+    /// Check the primer scheme against the reference and exit
+    #[arg(long)]
+    validate: bool,
+    /// Write a report of primers whose binding sites differ from the sample
+    #[arg(long)]
+    primer_report: Option<PathBuf>,
+    /// Write per-amplicon insert size and overlap score histograms
+    #[arg(long)]
+    histograms: Option<PathBuf>,
+    /// Write merged reads as FASTA
+    #[arg(long)]
+    merged: Option<PathBuf>,
+    /// Mask bases of merged reads with a quality below this as `N`
+    #[arg(long, default_value_t = 0)]
+    mask_below: u8,
+    /// Shortest overlap for merging mates
+    #[arg(long, default_value_t = MatingParams::default().min_overlap)]
+    min_overlap: usize,
+    /// Lowest overlap score for merging mates
+    #[arg(long, default_value_t = MatingParams::default().min_score)]
+    min_score: i16,
+    /// Score deducted for each mismatch in an overlap
+    #[arg(long, default_value_t = MatingParams::default().mismatch_penalty)]
+    mismatch_penalty: i16,
+    /// How far from the expected overlap to search
+    #[arg(long, default_value_t = MatingParams::default().max_span)]
+    max_span: usize,
+    /// Highest fraction of mismatches in an overlap
+    #[arg(long, default_value_t = MatingParams::default().max_mismatch_rate)]
+    max_mismatch_rate: f64,
+    /// Objective for scoring overlaps: score, hamming-rate or log-likelihood
+    #[arg(long, default_value_t = Objective::default())]
+    objective: Objective,
+    /// Score deducted for each base of a gap in an overlap
+    #[arg(long, default_value_t = MatingParams::default().gap_penalty)]
+    gap_penalty: i16,
+    /// Align overlaps allowing indels, eg. for homopolymer errors. Gapped
+    /// overlaps are only scored by matches, mismatches and gaps.
+    #[arg(long)]
+    gapped: bool,
+    /// Save the primer set annotated with the reference and exit. Paths ending
+    /// in `.json` are written as JSON, anything else in binary.
+    #[arg(long)]
+    save_primers: Option<PathBuf>,
+}
+
+fn save_primers(primers: &PrimerSet, path: &Path) {
+    let out = match File::create(path) {
+        Ok(file) => BufWriter::new(file),
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let saved = match path.extension() {
+        Some(ext) if ext == "json" => primers.to_json(out),
+        _ => primers.to_binary(out),
+    };
+    if let Err(e) = saved {
+        eprintln!("{}: {}", path.display(), e);
+        std::process::exit(1);
+    }
+}
+
+fn read_reference(path: &Path) -> Reference {
+    let fasta: Fasta<BufReader<File>> = Fasta::new(BufReader::new(File::open(path).unwrap()));
+
+    let mut reference = Reference::new();
+    for record in fasta {
+        let record = record.unwrap();
+        let header = String::from_utf8_lossy(&record.fields);
+        let name = header.split_whitespace().next().unwrap_or("");
+        reference.push(name, record.seq);
+    }
+    reference
+}
+
+fn list_schemes() {
+    match schemes::summaries() {
+        Ok(summaries) => {
+            println!("scheme\tamplicons\tmin_length\tmax_length");
+            for s in summaries {
+                println!("{}\t{}\t{}\t{}", s.name, s.amplicons, s.min_len, s.max_len);
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
 fn main() {
     let args = Cli::parse();
 
-    //    let mut stats = Stats::new();
+    if args.gapped && args.objective != Objective::MismatchScore {
+        eprintln!(
+            "--gapped scores overlaps with the score objective, not {}",
+            args.objective
+        );
+        std::process::exit(1);
+    }
+
+    if args.list_schemes {
+        list_schemes();
+        return;
+    }
+
+    let mut stats = Stats::default();
+
+    // the reference is only read once something needs it, a saved primer set
+    // already carries its target sequences
+    let reference_path = args.reference.as_deref().unwrap();
+    let mut reference: Option<Reference> = None;
+
+    let loaded = match (&args.primers, &args.scheme) {
+        (Some(path), _) => PrimerSet::is_saved(path)
+            .and_then(|saved| Ok((PrimerSet::from_path(path, None)?, saved)))
+            .map_err(|e| format!("{}: {}", path.display(), e)),
+        (None, Some(name)) => PrimerSet::builtin(name)
+            .map(|primers| (primers, false))
+            .map_err(|e| format!("{}: {}", name, e)),
+        (None, None) => unreachable!("clap requires --primers or --scheme"),
+    };
+    let annotated = loaded.and_then(|(mut primers, saved)| {
+        if !primers.is_annotated() {
+            let reference = reference.get_or_insert_with(|| read_reference(reference_path));
+            primers
+                .annotate(reference)
+                .map_err(|e| format!("{}: {}", reference_path.display(), e))?;
+        }
+        Ok((primers, saved))
+    });
+    let primers = match annotated {
+        // saved primer sets keep their thresholds unless they are given
+        Ok((primers, saved)) => {
+            let (mismatches, max_offset) = if saved {
+                (primers.max_mismatches, primers.max_offset)
+            } else {
+                (3, 0)
+            };
+            primers
+                .with_max_mismatches(args.mismatches.unwrap_or(mismatches))
+                .with_max_offset(args.max_offset.unwrap_or(max_offset))
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    for (a, b) in &primers.collisions {
+        eprintln!("warning: primer {} is a prefix of {}", a, b);
+    }
+    if args.validate {
+        let reference = reference.get_or_insert_with(|| read_reference(reference_path));
+        let report = primers.validate(reference, primers.max_mismatches);
+        for issue in &report.issues {
+            eprintln!("warning: {}", issue);
+        }
+        std::process::exit(if report.is_ok() { 0 } else { 1 });
+    }
+    if let Some(path) = &args.save_primers {
+        save_primers(&primers, path);
+        return;
+    }
+    let reference = reference.unwrap_or_else(|| read_reference(reference_path));
 
     let fq1: Fastq<BufReader<MultiGzDecoder<File>>> = Fastq::new(BufReader::new(
-        MultiGzDecoder::new(File::open(&args.r1).unwrap()),
+        MultiGzDecoder::new(File::open(args.r1.as_ref().unwrap()).unwrap()),
     ));
     let fq2: Fastq<BufReader<MultiGzDecoder<File>>> = Fastq::new(BufReader::new(
-        MultiGzDecoder::new(File::open(&args.r2).unwrap()),
+        MultiGzDecoder::new(File::open(args.r2.as_ref().unwrap()).unwrap()),
     ));
 
-    let mut reference: Fasta<BufReader<File>> =
-        Fasta::new(BufReader::new(File::open(&args.reference).unwrap()));
+    let mating = MatingParams {
+        min_overlap: args.min_overlap,
+        min_score: args.min_score,
+        mismatch_penalty: args.mismatch_penalty,
+        max_span: args.max_span,
+        max_mismatch_rate: args.max_mismatch_rate,
+        objective: args.objective,
+        gap_penalty: args.gap_penalty,
+        gapped: args.gapped,
+    };
 
-    let ref_seq: Seq<Dna> = reference.next().unwrap().unwrap().seq; //    let aligner = Aligner::new(&reference.next().unwrap().unwrap().seq);
-
-    let primers = PrimerSet::from_csv(&args.primers, Some(&ref_seq));
     let mut f1r2 = 0;
     let mut f2r1 = 0;
     let mut r1f2 = 0;
@@ -64,8 +256,15 @@ fn main() {
     let bins: HashMap<(String, String), usize> = HashMap::new();
     //    let mut leftover: HashMap<Seq<Dna>, usize> = HashMap::new();
     //    let mut tree: IntervalTree<usize, Vec<u8>> = IntervalTree::new();
-    let mut tree: IntervalTree<usize, ()> = IntervalTree::new();
-    let mut ibins: HashMap<Interval<usize>, HashMap<Seq<Dna>, Assembly>> = HashMap::new();
+    let mut trees: HashMap<Option<String>, IntervalTree<usize, ()>> = HashMap::new();
+    let mut ibins: HashMap<(Option<String>, Interval<usize>), HashMap<Seq<Dna>, Assembly>> =
+        HashMap::new();
+    let mut interval_targets: HashMap<(Option<String>, Interval<usize>), String> = HashMap::new();
+    let mut consensus: Vec<Consensus> = Vec::new();
+    let mut merged_out = args
+        .merged
+        .as_ref()
+        .map(|path| BufWriter::new(File::create(path).unwrap()));
 
     for (r1, r2) in fq1.zip(fq2) {
         match (r1, r2) {
@@ -76,14 +275,30 @@ fn main() {
             }
             (Ok(r1), Ok(r2)) => {
                 total += 1;
-                if total > 1000 {
-                    println!("ending early");
-                    break;
-                }
-                match primers.get_amplicon(&r1.seq, &r2.seq) {
-                    Merged(orientation, p1, p2, seq) => {
-                        let start = p1.index;
-                        let end = p2.index;
+                let amplicon = match (&r1.quality, &r2.quality) {
+                    (Some(q1), Some(q2)) => {
+                        let q1: Vec<u8> = q1.iter().map(|q| u8::from(*q)).collect();
+                        let q2: Vec<u8> = q2.iter().map(|q| u8::from(*q)).collect();
+                        primers.get_amplicon_qual(&r1.seq, &q1, &r2.seq, &q2, &mating, &mut stats)
+                    }
+                    _ => primers.get_amplicon(&r1.seq, &r2.seq, &mating, &mut stats),
+                };
+                match &amplicon {
+                    Merged(orientation, p1, _, seq, qual) => {
+                        if let Some(out) = &mut merged_out {
+                            let header = String::from_utf8_lossy(&r1.fields);
+                            let name = header.split_whitespace().next().unwrap_or("");
+                            let masked = mask(seq, qual, args.mask_below);
+                            writeln!(out, ">{} {}\n{}", name, p1.target, masked).unwrap();
+                        }
+                        // bin by amplicon insert so that alternate primers share a bin,
+                        // primer regions come from overlapping amplicons
+                        let (target, seq): (_, Seq<Dna>) = match primers.amplicon_insert(&amplicon)
+                        {
+                            Some((target, insert)) => (target, insert.into()),
+                            None => continue,
+                        };
+                        let (start, end) = (target.insert_start, target.insert_end);
                         merged += 1;
                         match orientation {
                             F1R2 => f1r2 += 1,
@@ -93,9 +308,15 @@ fn main() {
                         };
                         let interval = Interval::new(Included(start), Included(end));
 
-                        tree.insert(interval.clone(), ());
+                        interval_targets
+                            .entry((target.chrom.clone(), interval.clone()))
+                            .or_insert_with(|| target.name.clone());
+                        trees
+                            .entry(target.chrom.clone())
+                            .or_insert_with(IntervalTree::new)
+                            .insert(interval.clone(), ());
                         ibins
-                            .entry(interval)
+                            .entry((target.chrom.clone(), interval))
                             .or_insert(HashMap::from([(
                                 seq.clone(),
                                 Assembly {
@@ -128,50 +349,140 @@ fn main() {
         }
     }
 
-    println!(
-        "{:?}",
-        tree.intervals_between(&Interval::point(14), &Interval::point(29000))
-    );
-
     for (k, v) in bins {
         if v > 100 {
             println!("{:?}: {}", k, v);
         }
     }
 
-    for interval in tree.intervals() {
-        let bin = ibins.get(&interval).unwrap();
+    // unnamed targets belong to the first reference record
+    let mut chroms: Vec<Option<String>> = reference.names().map(|n| Some(n.to_string())).collect();
+    chroms.push(None);
 
-        for (k, v) in merge_bin(bin) {
-            if v.end - v.start > 400 || k.len() > 400 {
-                continue;
-            }
-            let ref_seg: &SeqSlice<Dna> = &ref_seq[v.start..v.end];
-            if v.count > 5 {
-                let (score, ops) = edit_dist(&ref_seg.to_string(), &k.to_string());
-                if score > 0 {
+    for chrom in chroms {
+        let tree = match trees.get(&chrom) {
+            Some(tree) => tree,
+            None => continue,
+        };
+        let ref_seq = reference.get(chrom.as_deref()).unwrap();
+        let chrom_name = chrom.as_deref().or(reference.names().next()).unwrap_or("");
+
+        for interval in tree.intervals() {
+            let key = (chrom.clone(), interval);
+            let bin = ibins.get(&key).unwrap();
+
+            for (k, v) in merge_bin(bin) {
+                if v.end - v.start > 400 || k.len() > 400 {
+                    continue;
+                }
+                let ref_seg: &SeqSlice<Dna> = &ref_seq[v.start..v.end];
+                if v.count > 5 {
+                    consensus.push(Consensus {
+                        target: interval_targets[&key].clone(),
+                        chrom: chrom.clone(),
+                        start: v.start,
+                        end: v.end,
+                        seq: k.clone(),
+                        count: v.count,
+                    });
+                    let (score, ops) = edit_dist(&ref_seg.to_string(), &k.to_string());
+                    if score > 0 {
+                        println!(
+                            "\n\nDISTANCE:\n{}\n{}\n{}\n\n",
+                            &ref_seg,
+                            pp(&ops.iter().map(|(_, op)| *op).collect()),
+                            &k
+                        );
+                    }
                     println!(
-                        "\n\nDISTANCE:\n{}\n{}\n{}\n\n",
-                        &ref_seg,
-                        pp(&ops.iter().map(|(_, op)| *op).collect()),
-                        &k
+                        ">{}:{}-{},ref_length:{},count:{},lenth:{}\n{}",
+                        chrom_name,
+                        v.start,
+                        v.end,
+                        v.end - v.start,
+                        &v.count,
+                        &k.len(),
+                        &k.to_string(),
+                        //String::from_utf8_lossy(&k[20..k.len() - 20]),
                     );
                 }
-                println!(
-                    ">{}-{},ref_length:{},count:{},lenth:{}\n{}",
-                    v.start,
-                    v.end,
-                    v.end - v.start,
-                    &v.count,
-                    &k.len(),
-                    &k.to_string(),
-                    //String::from_utf8_lossy(&k[20..k.len() - 20]),
-                );
+            }
+        }
+
+        let mut covering: Vec<&Consensus> = consensus.iter().filter(|c| c.chrom == chrom).collect();
+        covering.sort_by(|a, b| b.count.cmp(&a.count));
+        let inserts: Vec<(usize, &SeqSlice<Dna>)> = covering
+            .iter()
+            .map(|c| -> (usize, &SeqSlice<Dna>) { (c.start, &c.seq) })
+            .collect();
+        println!(
+            ">{} consensus\n{}",
+            chrom_name,
+            tile(ref_seq.len(), &inserts)
+        );
+    }
+
+    if let Some(path) = &args.primer_report {
+        let mut out = File::create(path).unwrap();
+        writeln!(out, "primer\ttarget\tmismatches\tcoverage").unwrap();
+        for report in primer_site_report(&primers, &consensus) {
+            writeln!(out, "{}", report).unwrap();
+        }
+    }
+
+    if let Some(path) = &args.histograms {
+        let mut out = File::create(path).unwrap();
+        writeln!(out, "target\thistogram\tvalue\tcount").unwrap();
+        let overlaps = match mating.objective {
+            Objective::HammingRate => "overlap_mismatch_percent",
+            _ => "overlap_score",
+        };
+        let mut targets: Vec<&String> = stats.insert_sizes.keys().collect();
+        targets.extend(stats.overlap_scores.keys());
+        targets.extend(stats.unscored.keys());
+        targets.sort();
+        targets.dedup();
+        for target in targets {
+            for (len, count) in stats.insert_sizes.get(target).into_iter().flatten() {
+                writeln!(out, "{}\tinsert_size\t{}\t{}", target, len, count).unwrap();
+            }
+            for (bin, count) in stats.overlap_scores.get(target).into_iter().flatten() {
+                writeln!(out, "{}\t{}\t{}\t{}", target, overlaps, bin, count).unwrap();
+            }
+            if let Some(count) = stats.unscored.get(target) {
+                writeln!(out, "{}\t{}\tNA\t{}", target, overlaps, count).unwrap();
             }
         }
     }
+
     eprintln!(
         "r1f2: {}\tf1r2: {}\tr2f1: {}\tf2r1: {}\tmerged: {}\ttotal: {}\tinvalid: {}",
         r1f2, f1r2, r2f1, f2r1, merged, total, invalid_reads
     );
+    eprintln!(
+        "matched: {}\tambiguous primers: {}\tmated: {}",
+        stats.matched, stats.ambiguous, stats.mated
+    );
+    eprintln!(
+        "on target: {}\toff target: {}\tchimeric pairs: {}",
+        stats.on_target, stats.off_target, stats.chimeric
+    );
+    for ((a, b), count) in stats.top_dimers(10) {
+        eprintln!("primer dimer\t{}\t{}\t{}", a, b, count);
+    }
+    for ((a, b), count) in stats.top_chimeras(10) {
+        eprintln!("chimeric pair\t{}\t{}\t{}", a, b, count);
+    }
+    for ((a, b), count) in stats.top_spurious(10) {
+        eprintln!("spurious pair\t{}\t{}\t{}", a, b, count);
+    }
+    for ((a, b), count) in &stats.pool_pairs {
+        eprintln!("pools\t{:?}\t{:?}\t{}", a, b, count);
+    }
+    for (canonical, name, count) in stats.alt_usage() {
+        eprintln!("alt primer usage\t{}\t{}\t{}", canonical, name, count);
+    }
+    for (name, site, count) in stats.top_inexact(10) {
+        eprintln!("inexact primer site\t{}\t{}\t{}", name, site, count);
+    }
 }